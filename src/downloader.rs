@@ -1,21 +1,29 @@
 #![allow(dead_code, unused)]
-use crate::tweet_db::TweetDB;
+use crate::media_archive::ArchiveFormat;
+use crate::tweet_db::{DBTuning, MediaFail, TweetDB, TweetStore};
 use crate::tweet_fetcher::TweetDownloadDB;
 use crate::utils::Error;
-use crate::utils::{extract_twitter_url, read_url_list};
+use crate::utils::{
+    dedup_filename, extract_twitter_url, filename_owners, media_filename, read_url_list,
+    sanitize_path_component,
+};
 use anyhow::Result;
-use clap::{CommandFactory, Parser, ValueHint};
+use clap::{CommandFactory, Parser, ValueEnum, ValueHint};
 use console::{Emoji, Style};
 use indicatif::{ProgressBar, ProgressStyle};
 use lazy_static::lazy_static;
 use log::{info, warn, LevelFilter};
 use rayon::prelude::*;
+use rusqlite::params;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::sync::{mpsc, Arc, Mutex};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
+mod media_archive;
 mod tweet_db;
 mod tweet_fetcher;
 mod tweet_parser;
@@ -23,21 +31,12 @@ mod twitter_def;
 mod utils;
 
 use regex::Regex;
-use shirodl;
 use shirodl::{DownloadFailed, DownloadTask};
 
 lazy_static! {
     static ref EXTRACTOR: Regex = Regex::new(r#".*/(.*?)(\?.*|$)"#).unwrap();
 }
 
-fn extract_fn(url: &str) -> &str {
-    if let Some(m) = EXTRACTOR.captures(url) {
-        m.get(1).unwrap().as_str()
-    } else {
-        ""
-    }
-}
-
 fn is_need_orig(url: &str) -> bool {
     if let Some(m) = EXTRACTOR.captures(url) {
         if let Some(v) = m.get(2) {
@@ -45,11 +44,7 @@ fn is_need_orig(url: &str) -> bool {
             if filename.ends_with(".mp4") {
                 false
             } else {
-                if v.as_str().len() > 1 {
-                    false
-                } else {
-                    true
-                }
+                v.as_str().len() <= 1
             }
         } else {
             false
@@ -59,54 +54,594 @@ fn is_need_orig(url: &str) -> bool {
     }
 }
 
-fn run_downloader<P: AsRef<Path>>(twdb: P, dest_dir: P) -> Result<()> {
-    let dest_dir = dest_dir.as_ref();
-    if !dest_dir.exists() {
-        std::fs::create_dir_all(dest_dir);
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum FailuresFormat {
+    /// Json list which can be fed back with --retry-file
+    Json,
+    /// `url ==> path/filename` lines
+    Text,
+}
+
+/// One unrecoverable download in the json failures file.
+#[derive(Serialize, Deserialize)]
+struct FailedItem {
+    url: String,
+    path: String,
+    filename: String,
+    #[serde(default)]
+    media_id: Option<String>,
+}
+
+/// Paces finished downloads so the average speed stays under `bytes_per_sec`.
+/// Sleeping in the completion callback holds back the worker before its next request.
+struct Throttle {
+    bytes_per_sec: u64,
+    started: Instant,
+    downloaded: Mutex<u64>,
+}
+
+impl Throttle {
+    fn new(bytes_per_sec: u64) -> Self {
+        Self {
+            bytes_per_sec,
+            started: Instant::now(),
+            downloaded: Mutex::new(0),
+        }
+    }
+
+    fn consume(&self, bytes: u64) {
+        let due = {
+            let mut downloaded = self.downloaded.lock().unwrap();
+            *downloaded += bytes;
+            Duration::from_secs_f64(*downloaded as f64 / self.bytes_per_sec as f64)
+        };
+        let elapsed = self.started.elapsed();
+        if due > elapsed {
+            thread::sleep(due - elapsed);
+        }
+    }
+}
+
+/// `folder/filename` of a task, the key of `media_ids` and of the media tables.
+fn media_key(path: &Path, filename: &str) -> String {
+    let folder = path.file_name().unwrap_or_default().to_string_lossy();
+    if folder.is_empty() {
+        // --flat
+        filename.to_string()
+    } else {
+        format!("{}/{}", folder, filename)
+    }
+}
+
+/// `media_key` of every file in `dir` and its direct subfolders, read without a stat per file.
+fn index_existing_files(dir: &Path) -> Result<HashSet<String>> {
+    let mut files = HashSet::new();
+    if !dir.is_dir() {
+        return Ok(files);
+    }
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if !entry.file_type()?.is_dir() {
+            // --flat
+            files.insert(name);
+            continue;
+        }
+        for file in std::fs::read_dir(entry.path())? {
+            let file = file?;
+            if !file.file_type()?.is_dir() {
+                files.insert(format!("{}/{}", name, file.file_name().to_string_lossy()));
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// Whether a media passes `--min-width` and `--min-height`, 0 is an unknown dimension.
+fn is_size_wanted(width: u64, height: u64, min_size: (u64, u64), include_unknown: bool) -> bool {
+    if width == 0 || height == 0 {
+        include_unknown
+    } else {
+        width >= min_size.0 && height >= min_size.1
+    }
+}
+
+/// Card images keep their format in the query, `card_img/<id>/<key>?format=jpg&name=orig`.
+fn url_format(url: &str) -> Option<&str> {
+    let query = url.split_once('?')?.1;
+    query.split('&').find_map(|v| v.strip_prefix("format="))
+}
+
+/// (folder, filename) of a tweet media, `author/<media name>` or `author_tweetid_no.ext` if flat.
+fn media_location(author: &str, tweet_id: u64, no: i32, url: &str, flat: bool) -> (String, String) {
+    let author = sanitize_path_component(author);
+    let mut filename = media_filename(url);
+    if Path::new(&filename).extension().is_none() {
+        if let Some(format) = url_format(url) {
+            filename = format!("{}.{}", filename, format);
+        }
+    }
+    if flat {
+        let ext = Path::new(&filename)
+            .extension()
+            .map(|v| format!(".{}", v.to_string_lossy()))
+            .unwrap_or_default();
+        (
+            String::new(),
+            format!("{}_{}_{}{}", author, tweet_id, no, ext),
+        )
+    } else {
+        (author, filename)
+    }
+}
+
+/// Hash a finished download and store it for `verify-archive`, failures are only logged.
+fn record_checksum(
+    twdb: &TweetDB,
+    media_ids: &HashMap<String, String>,
+    download_dir: &Path,
+    path: &Path,
+    filename: &str,
+) {
+    let rel_path = media_key(path, filename);
+    let media_id = if let Some(v) = media_ids.get(&rel_path) {
+        v
+    } else {
+        return;
+    };
+    let result = std::fs::File::open(download_dir.join(path).join(filename))
+        .map_err(|e| e.into())
+        .and_then(utils::checksum)
+        .and_then(|digest| {
+            twdb.set_media_checksum(media_id, &rel_path, utils::CHECKSUM_ALGO, &digest)
+        });
+    if let Err(e) = result {
+        warn!("Cannot record checksum of {}: {}", rel_path, e);
+    }
+}
+
+fn url_host(url: &str) -> &str {
+    let rest = url.split_once("://").map_or(url, |v| v.1);
+    rest.split(['/', '?']).next().unwrap_or("")
+}
+
+/// Split `tasks` into the ones of uncapped hosts and, for every capped host, its limit and tasks.
+fn split_by_host(
+    tasks: Vec<DownloadTask>,
+    host_limits: &HashMap<String, usize>,
+) -> (Vec<DownloadTask>, Vec<(usize, Vec<DownloadTask>)>) {
+    let mut uncapped = vec![];
+    let mut capped: HashMap<String, Vec<DownloadTask>> = HashMap::new();
+    for task in tasks {
+        let host = url_host(&task.url);
+        if host_limits.contains_key(host) {
+            capped.entry(host.to_string()).or_default().push(task);
+        } else {
+            uncapped.push(task);
+        }
+    }
+    let capped = capped
+        .into_iter()
+        .map(|(host, tasks)| (host_limits[&host], tasks))
+        .collect();
+    (uncapped, capped)
+}
+
+/// What the completion callback of every downloader needs.
+#[derive(Clone)]
+struct DownloadContext {
+    download_dir: PathBuf,
+    sender: mpsc::Sender<Option<String>>,
+    throttle: Option<Arc<Throttle>>,
+    twdb: Option<TweetDB>,
+    media_ids: Arc<HashMap<String, String>>,
+}
+
+fn download_tasks(tasks: Vec<DownloadTask>, ctx: DownloadContext) -> Vec<DownloadFailed> {
+    let mut downloader = shirodl::Downloader::new();
+    downloader.set_destination(ctx.download_dir.clone());
+    downloader.set_auto_rename(false);
+    downloader.set_timeout(Duration::from_secs(60));
+    for task in tasks {
+        downloader.append_task(task);
     }
-    let twdb = TweetDB::new(twdb.as_ref())?;
-    let conn = twdb.get_db_conn();
-    let mut stmt = conn.prepare(
-        r#"SELECT t.author, m.url
-                    FROM tweet AS t INNER JOIN media as m
-                    WHERE t.id == m.tweet_id"#,
-    )?;
-    let mut tasks: Vec<DownloadTask> = stmt
-        .query_map([], |row| {
-            Ok((
-                row.get::<_, String>(0).unwrap(),
-                row.get::<_, String>(1).unwrap(),
-            ))
-        })?
-        .filter_map(|v| {
-            let (author, url) = v.unwrap();
-            let url = if is_need_orig(&url) {
-                url + "?name=orig"
+
+    downloader
+        .download(move |url, path, filename, err| {
+            if let (Some(throttle), Some(filename)) = (&ctx.throttle, filename) {
+                let file = ctx.download_dir.join(path).join(filename);
+                let size = std::fs::metadata(file).map(|m| m.len()).unwrap_or(0);
+                throttle.consume(size);
+            }
+            if let (Some(twdb), Some(filename), None) = (&ctx.twdb, filename, err) {
+                record_checksum(twdb, &ctx.media_ids, &ctx.download_dir, path, filename);
+                if let Err(e) = twdb.remove_media_fail(&media_key(path, filename)) {
+                    warn!("Cannot clear media fail of {}: {}", filename, e);
+                }
+            }
+            let msg_style = if let Some(e) = err {
+                if e.ignorable() {
+                    Style::new().black().bright()
+                } else {
+                    Style::new().red().bright().bold()
+                }
             } else {
-                url
+                Style::new().green()
             };
-            let filename = extract_fn(&url).to_string();
-            // println!("{}/{} <== {}", author, filename, url);
-            if dest_dir.join(&author).join(&filename).exists() {
-                None
-            } else {
-                Some((url, PathBuf::from(author), Some(filename)).into())
-            }
+            let msg = match err {
+                None => format!("{} {}", Emoji::new("✔️", "[ Done ]"), url),
+                Some(e) => format!("{} {} [{}]", Emoji::new("❌️", "[Failed]"), url, e),
+            };
+            ctx.sender.send(Some(msg_style.apply_to(msg).to_string()));
         })
-        .collect();
+        .unwrap()
+}
+
+/// Download `tasks` with every host capped in `host_limits` held to its own number of
+/// simultaneous downloads. Each capped host gets that many workers handing one task at a time
+/// to a downloader, the rest go to a single downloader running beside them.
+fn download_limited(
+    tasks: Vec<DownloadTask>,
+    host_limits: &HashMap<String, usize>,
+    ctx: &DownloadContext,
+) -> Vec<DownloadFailed> {
+    let (uncapped, capped) = split_by_host(tasks, host_limits);
+    let mut workers = vec![];
+    if !uncapped.is_empty() {
+        let ctx = ctx.clone();
+        workers.push(thread::spawn(move || download_tasks(uncapped, ctx)));
+    }
+    for (limit, tasks) in capped {
+        let workers_count = limit.min(tasks.len());
+        let queue = Arc::new(Mutex::new(VecDeque::from(tasks)));
+        for _ in 0..workers_count {
+            let queue = queue.clone();
+            let ctx = ctx.clone();
+            workers.push(thread::spawn(move || {
+                let mut faileds = vec![];
+                loop {
+                    let task = if let Some(v) = queue.lock().unwrap().pop_front() {
+                        v
+                    } else {
+                        break;
+                    };
+                    faileds.extend(download_tasks(vec![task], ctx.clone()));
+                }
+                faileds
+            }));
+        }
+    }
+    workers
+        .into_iter()
+        .flat_map(|v| v.join().unwrap())
+        .collect()
+}
+
+fn parse_host_limit(s: &str) -> std::result::Result<(String, usize), String> {
+    let (host, limit) = s
+        .split_once('=')
+        .ok_or_else(|| "expect HOST=N".to_string())?;
+    match limit.parse::<usize>() {
+        Ok(limit) if limit > 0 => Ok((host.to_string(), limit)),
+        _ => Err(format!("`{}` is not a positive number", limit)),
+    }
+}
+
+const AVATAR_FOLDER: &str = "avatars";
+
+const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(10 * 60);
+
+/// Wait before the `attempt`th retry of a media, doubling from `base` each time.
+fn retry_delay(base: Duration, attempt: u32) -> Duration {
+    base.saturating_mul(1 << (attempt - 1).min(16))
+        .min(MAX_RETRY_BACKOFF)
+}
+
+/// Avatar urls point to the 48x48 `_normal` variant, strip it to get the original upload.
+fn original_avatar_url(url: &str) -> String {
+    lazy_static! {
+        static ref SIZE_SUFFIX: Regex = Regex::new(r#"_normal(\.\w+)?$"#).unwrap();
+    }
+    SIZE_SUFFIX.replace(url, "$1").to_string()
+}
+
+/// Write `tasks` as an aria2 input file, `aria2c -i` then downloads them into `download_dir`.
+fn write_aria2_input(path: &Path, tasks: &[DownloadTask], download_dir: &Path) -> Result<()> {
+    // aria2 resolves relative dirs against its own working dir
+    let download_dir = std::fs::canonicalize(download_dir)?;
+    let mut content = String::new();
+    for task in tasks {
+        content += &format!(
+            "{}\n  dir={}\n",
+            task.url,
+            download_dir.join(&task.path).display()
+        );
+        if let Some(filename) = &task.filename {
+            content += &format!("  out={}\n", filename);
+        }
+    }
+    std::fs::write(path, content)?;
+    Ok(())
+}
+
+fn run_downloader(args: Args, dest_dir: PathBuf) -> Result<()> {
+    let Args {
+        tweet_db: twdb,
+        archive_format,
+        download_avatars,
+        retry_file,
+        retry_failed_media,
+        failures_format,
+        db_tuning,
+        rate_limit,
+        concurrency_per_host,
+        download_db: dldb,
+        follow_quote_depth,
+        export_aria2,
+        flat,
+        max_attempts,
+        retry_backoff,
+        index_existing,
+        min_width,
+        min_height,
+        include_unknown_size,
+        ..
+    } = args;
+    let host_limits: HashMap<String, usize> = concurrency_per_host.into_iter().collect();
+    let retry_backoff = Duration::from_secs(retry_backoff);
+    let min_size = (min_width, min_height);
+    let dest_dir = dest_dir.as_path();
+    if !dest_dir.exists() {
+        std::fs::create_dir_all(dest_dir);
+    }
+    // Archives can't be handed to shirodl, download into a staging dir and pack afterwards.
+    let download_dir = if archive_format == ArchiveFormat::None {
+        dest_dir.to_path_buf()
+    } else {
+        dest_dir.join(".staging")
+    };
+    let mut archived: HashMap<String, HashSet<String>> = HashMap::new();
+    // "folder/filename" => media id, to record checksums of finished downloads
+    let mut media_ids: HashMap<String, String> = HashMap::new();
+    // a retry file may be used without the TweetDB, checksums are skipped then
+    let twdb = if twdb.is_file() {
+        Some(TweetDB::with_tuning(&twdb, db_tuning)?)
+    } else {
+        None
+    };
+    let existing = if index_existing {
+        let existing = index_existing_files(&download_dir)?;
+        info!(
+            "Indexed {} existing files in {}.",
+            existing.len(),
+            download_dir.display()
+        );
+        Some(existing)
+    } else {
+        None
+    };
+    let mut skipped = 0;
+    // media keys handed out so far, a media shared by several tweets of the author is one file
+    let mut queued: HashSet<String> = HashSet::new();
+    // (folder, url, filename) => task, None if already downloaded or queued
+    let mut make_task = |folder: String, url: String, filename: String| -> Option<DownloadTask> {
+        // println!("{}/{} <== {}", folder, filename, url);
+        if !queued.insert(media_key(Path::new(&folder), &filename)) {
+            return None;
+        }
+        let in_archive = archive_format != ArchiveFormat::None
+            && archived
+                .entry(folder.clone())
+                .or_insert_with(|| {
+                    let path = media_archive::archive_path(dest_dir, &folder, archive_format);
+                    media_archive::list_entries(&path, archive_format).unwrap_or_else(|e| {
+                        warn!("Cannot read archive {}: {}", path.display(), e);
+                        HashSet::new()
+                    })
+                })
+                .contains(&filename);
+        let on_disk = match &existing {
+            Some(existing) => existing.contains(&media_key(Path::new(&folder), &filename)),
+            None => download_dir.join(&folder).join(&filename).exists(),
+        };
+        if in_archive || on_disk {
+            skipped += 1;
+            None
+        } else {
+            Some((url, PathBuf::from(folder), Some(filename)).into())
+        }
+    };
+
+    let mut tasks: Vec<DownloadTask> = vec![];
+    if let Some(retry_file) = retry_file {
+        info!("Retrying failures from {}", retry_file.display());
+        let failures: Vec<FailedItem> =
+            serde_json::from_str(&std::fs::read_to_string(&retry_file)?)?;
+        tasks.extend(failures.into_iter().filter_map(|v| {
+            if let Some(media_id) = v.media_id {
+                media_ids.insert(media_key(Path::new(&v.path), &v.filename), media_id);
+            }
+            make_task(v.path, v.url, v.filename)
+        }));
+    } else if retry_failed_media {
+        let twdb = twdb.as_ref().unwrap();
+        let fails = twdb.get_media_fails()?;
+        info!("Retrying {} failed media from TweetDB.", fails.len());
+        for fail in fails {
+            // flat downloads have no folder
+            let (folder, filename) = fail.path.rsplit_once('/').unwrap_or(("", &fail.path));
+            if let Some(media_id) = &fail.media_id {
+                media_ids.insert(fail.path.clone(), media_id.clone());
+            }
+            match make_task(folder.to_string(), fail.url, filename.to_string()) {
+                Some(task) => tasks.push(task),
+                // got it some other way since
+                None => twdb.remove_media_fail(&fail.path)?,
+            }
+        }
+    } else {
+        let conn = twdb.as_ref().unwrap().get_db_conn();
+        let mut stmt = conn.prepare(
+            r#"SELECT DISTINCT t.author, m.url, m.id, t.id, m.no
+                        FROM tweet AS t INNER JOIN media as m
+                        WHERE t.id == m.tweet_id
+                        AND CASE WHEN IFNULL(m.width, 0) = 0 OR IFNULL(m.height, 0) = 0 THEN ?3
+                            ELSE m.width >= ?1 AND m.height >= ?2 END
+                        ORDER BY t.id, m.no"#,
+        )?;
+        // (folder, url, filename, media id), named once every media is known
+        let mut medias = stmt
+            .query_map(
+                params![min_size.0, min_size.1, include_unknown_size],
+                |row| {
+                    Ok((
+                        row.get::<_, String>(0).unwrap(),
+                        row.get::<_, String>(1).unwrap(),
+                        row.get::<_, String>(2).unwrap(),
+                        row.get::<_, u64>(3).unwrap(),
+                        row.get::<_, Option<i32>>(4).unwrap().unwrap_or(0),
+                    ))
+                },
+            )?
+            .map(|v| {
+                let (author, url, media_id, tweet_id, no) = v.unwrap();
+                let url = if is_need_orig(&url) {
+                    url + "?name=orig"
+                } else {
+                    url
+                };
+                let (folder, filename) = media_location(&author, tweet_id, no, &url, flat);
+                (folder, url, filename, media_id)
+            })
+            .collect::<Vec<(String, String, String, String)>>();
+
+        // quoted tweets are not stored in TweetDB, only in the raw json
+        if follow_quote_depth > 0 {
+            info!(
+                "Collecting media of quoted tweets up to {} levels.",
+                follow_quote_depth
+            );
+            let known = medias
+                .iter()
+                .map(|v| v.3.clone())
+                .collect::<HashSet<String>>();
+            let mut quoted_medias = vec![];
+            let twdb = twdb.as_ref().unwrap();
+            TweetDownloadDB::with_tuning(&dldb, db_tuning).for_each_json(|id, json| {
+                // only the tweets this TweetDB holds
+                if !twdb.is_exist(id) {
+                    return;
+                }
+                let tweets = match tweet_parser::parse_tweet_json(id, json) {
+                    Ok(v) => v,
+                    Err(_) => return,
+                };
+                for tweet in tweets.values() {
+                    let mut quoted = tweet.quoted();
+                    for _ in 0..follow_quote_depth {
+                        let tweet = match quoted {
+                            Some(v) => v,
+                            None => break,
+                        };
+                        let author = tweet.as_tweet().author;
+                        quoted_medias.extend(
+                            tweet
+                                .get_medias(None)
+                                .into_iter()
+                                .filter(|v| {
+                                    is_size_wanted(
+                                        v.width,
+                                        v.height,
+                                        min_size,
+                                        include_unknown_size,
+                                    )
+                                })
+                                .map(|v| (author.clone(), v.url, v.id, v.tweet_id, v.no)),
+                        );
+                        quoted = tweet.quoted();
+                    }
+                }
+            })?;
+            quoted_medias.sort();
+            quoted_medias.dedup();
+            medias.extend(quoted_medias.into_iter().filter_map(
+                |(author, url, media_id, tweet_id, no)| {
+                    if known.contains(&media_id) {
+                        return None;
+                    }
+                    let url = if is_need_orig(&url) {
+                        url + "?name=orig"
+                    } else {
+                        url
+                    };
+                    let (folder, filename) = media_location(&author, tweet_id, no, &url, flat);
+                    Some((folder, url, filename, media_id))
+                },
+            ));
+        }
+
+        let owners = filename_owners(medias.iter().map(|(folder, _, filename, media_id)| {
+            (folder.as_str(), filename.as_str(), media_id.as_str())
+        }));
+        tasks.extend(
+            medias
+                .into_iter()
+                .filter_map(|(folder, url, filename, media_id)| {
+                    let filename = dedup_filename(&owners, &folder, filename, &media_id);
+                    media_ids.insert(media_key(Path::new(&folder), &filename), media_id);
+                    make_task(folder, url, filename)
+                }),
+        );
+
+        if download_avatars {
+            let mut stmt = conn.prepare(
+                r#"SELECT screen_name, avatar_url FROM user
+                        WHERE avatar_url IS NOT NULL AND avatar_url != ''"#,
+            )?;
+            let avatars = stmt
+                .query_map([], |row| {
+                    Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+                })?
+                .map(|v| v.unwrap())
+                .collect::<Vec<(String, String)>>();
+            tasks.extend(avatars.into_iter().filter_map(|(screen_name, url)| {
+                let url = original_avatar_url(&url);
+                let filename = format!(
+                    "{}_{}",
+                    sanitize_path_component(&screen_name),
+                    media_filename(&url)
+                );
+                make_task(AVATAR_FOLDER.to_string(), url, filename)
+            }));
+        }
+    }
+
+    // the bar only counts what is left to download
+    eprintln!(
+        "{} already downloaded, {} to download.",
+        skipped,
+        tasks.len()
+    );
+
+    if let Some(export_aria2) = export_aria2 {
+        write_aria2_input(&export_aria2, &tasks, &download_dir)?;
+        eprintln!(
+            "Tasks written to {}, download them with `aria2c -i`.",
+            export_aria2.display()
+        );
+        return Ok(());
+    }
 
     let mut unrecoverables: Vec<DownloadTask> = vec![];
+    // media key => downloads failed so far
+    let mut attempts: HashMap<String, u32> = HashMap::new();
+    // failures to retry once their backoff is over
+    let mut waiting: Vec<(Instant, DownloadTask)> = vec![];
+    let throttle = rate_limit.map(|v| Arc::new(Throttle::new(v)));
+    let media_ids = Arc::new(media_ids);
 
     loop {
-        let mut downloader = shirodl::Downloader::new();
-        downloader.set_destination(dest_dir.to_path_buf());
-        downloader.set_auto_rename(false);
-        downloader.set_timeout(Duration::from_secs(60));
         let bar = ProgressBar::new(tasks.len() as u64);
-        while !tasks.is_empty() {
-            downloader.append_task(tasks.pop().unwrap());
-        }
-
         bar.set_style(
             ProgressStyle::default_bar()
                 .template(
@@ -128,76 +663,109 @@ fn run_downloader<P: AsRef<Path>>(twdb: P, dest_dir: P) -> Result<()> {
             }
         });
 
-        let faileds = downloader
-            .download(move |url, path, filename, err| {
-                let msg_style = if let Some(e) = err {
-                    if e.ignorable() {
-                        Style::new().black().bright()
-                    } else {
-                        Style::new().red().bright().bold()
-                    }
-                } else {
-                    Style::new().green()
-                };
-                let msg = format!(
-                    "{} {}",
-                    if err.is_none() {
-                        Emoji::new("✔️", "[ Done ]")
-                    } else {
-                        Emoji::new("❌️", "[Failed]")
-                    },
-                    if err.is_none() {
-                        url.to_string()
-                    } else {
-                        format!("{} [{}]", url, err.unwrap())
-                    }
-                );
-                sender.send(Some(msg_style.apply_to(msg).to_string()));
-            })
-            .unwrap();
+        let ctx = DownloadContext {
+            download_dir: download_dir.clone(),
+            sender: sender.clone(),
+            throttle: throttle.clone(),
+            twdb: twdb.clone(),
+            media_ids: media_ids.clone(),
+        };
+        let faileds = download_limited(std::mem::take(&mut tasks), &host_limits, &ctx);
 
         retain_sender.send(None);
         display_thread.join().unwrap();
 
-        let faileds: Vec<DownloadTask> = faileds
-            .into_iter()
-            .filter_map(|failed| {
-                let err = &failed.err;
-                if let shirodl::Error::ResourceNotFound = err {
-                    unrecoverables.push(failed.into());
-                    None
-                } else {
-                    Some(failed.into())
+        let now = Instant::now();
+        for failed in faileds {
+            let path = media_key(&failed.path, failed.filename.as_deref().unwrap_or(""));
+            let attempt = attempts.entry(path.clone()).or_insert(0);
+            *attempt += 1;
+            let err = &failed.err;
+            let not_found = matches!(err, shirodl::Error::ResourceNotFound);
+            if not_found || *attempt >= max_attempts {
+                if let Some(twdb) = &twdb {
+                    let fail = MediaFail {
+                        media_id: media_ids.get(&path).cloned(),
+                        path,
+                        url: failed.url.clone(),
+                        error: err.to_string(),
+                    };
+                    if let Err(e) = twdb.set_media_fail(&fail) {
+                        warn!("Cannot record media fail of {}: {}", fail.path, e);
+                    }
                 }
-            })
-            .collect();
+                unrecoverables.push(failed.into());
+            } else {
+                waiting.push((now + retry_delay(retry_backoff, *attempt), failed.into()));
+            }
+        }
 
-        if faileds.is_empty() {
-            break;
+        let next = match waiting.iter().map(|v| v.0).min() {
+            Some(v) => v,
+            None => break,
+        };
+        let delay = next.saturating_duration_since(Instant::now());
+        if !delay.is_zero() {
+            eprintln!(
+                "{} failed downloads to retry, next in {} secs.",
+                waiting.len(),
+                delay.as_secs()
+            );
+            thread::sleep(delay);
         }
-        tasks.extend(faileds.into_iter());
+        let now = Instant::now();
+        let (ready, later): (Vec<_>, Vec<_>) = std::mem::take(&mut waiting)
+            .into_iter()
+            .partition(|v| v.0 <= now);
+        waiting = later;
+        tasks.extend(ready.into_iter().map(|v| v.1));
+    }
+
+    if archive_format != ArchiveFormat::None {
+        media_archive::pack_staging(&download_dir, dest_dir, archive_format)?;
     }
 
     if !unrecoverables.is_empty() {
         let save_file = chrono::Local::now()
-            .format("%Y-%m-%d %H%M%S TweetDownloadFailures.txt")
+            .format(match failures_format {
+                FailuresFormat::Json => "%Y-%m-%d %H%M%S TweetDownloadFailures.json",
+                FailuresFormat::Text => "%Y-%m-%d %H%M%S TweetDownloadFailures.txt",
+            })
             .to_string();
-        println!(
+        eprintln!(
             "There are {} item cannot be download. Saved to file {}.",
             unrecoverables.len(),
             save_file
         );
-        let content: String = unrecoverables
-            .into_iter()
-            .map(|v| {
-                format!(
-                    "{} ==> {}/{}\n",
-                    v.url,
-                    v.path.display(),
-                    v.filename.unwrap_or("".to_string())
-                )
-            })
-            .collect();
+        let content: String = match failures_format {
+            FailuresFormat::Json => serde_json::to_string_pretty(
+                &unrecoverables
+                    .into_iter()
+                    .map(|v| {
+                        let filename = v.filename.unwrap_or("".to_string());
+                        let media_id = media_ids.get(&media_key(&v.path, &filename)).cloned();
+                        let path = v.path.to_string_lossy().to_string();
+                        FailedItem {
+                            url: v.url,
+                            media_id,
+                            path,
+                            filename,
+                        }
+                    })
+                    .collect::<Vec<FailedItem>>(),
+            )?,
+            FailuresFormat::Text => unrecoverables
+                .into_iter()
+                .map(|v| {
+                    format!(
+                        "{} ==> {}/{}\n",
+                        v.url,
+                        v.path.display(),
+                        v.filename.unwrap_or("".to_string())
+                    )
+                })
+                .collect(),
+        };
         std::fs::write(save_file, content).unwrap();
     }
     Ok(())
@@ -208,16 +776,112 @@ fn run_downloader<P: AsRef<Path>>(twdb: P, dest_dir: P) -> Result<()> {
 struct Args {
     #[clap(short = 't', long, default_value = "tw.sqlite", value_hint = ValueHint::FilePath)]
     tweet_db: PathBuf,
-    #[clap(default_value = "TweetMedias", value_hint = ValueHint::DirPath)]
-    dest_dir: PathBuf,
+    /// Defaults to `<db stem>_media` next to the TweetDB
+    #[clap(value_hint = ValueHint::DirPath)]
+    dest_dir: Option<PathBuf>,
+    #[clap(long, value_enum, default_value = "none")]
+    archive_format: ArchiveFormat,
+    /// Also download author avatars into the avatars folder
+    #[clap(long, action)]
+    download_avatars: bool,
+    /// Download the entries of a json failures file instead of the TweetDB media
+    #[clap(long, value_hint = ValueHint::FilePath)]
+    retry_file: Option<PathBuf>,
+    /// Only download the media recorded as failed in TweetDB by earlier runs
+    #[clap(long, action, conflicts_with = "retry-file")]
+    retry_failed_media: bool,
+    #[clap(long, value_enum, default_value = "json")]
+    failures_format: FailuresFormat,
+    /// Cap the average download speed, in bytes per second
+    #[clap(long, value_name = "BYTES/S", value_parser = clap::value_parser!(u64).range(1..))]
+    rate_limit: Option<u64>,
+    /// Download at most N files from HOST at the same time, can be repeated
+    #[clap(
+        long,
+        value_name = "HOST=N",
+        value_parser = parse_host_limit,
+        action = clap::ArgAction::Append
+    )]
+    concurrency_per_host: Vec<(String, usize)>,
+    /// Put the media under this sub folder of the destination, to keep archives apart
+    #[clap(long)]
+    namespace: Option<String>,
+    /// Use the TweetDB file name as the namespace
+    #[clap(long, action, conflicts_with = "namespace")]
+    namespace_from_db: bool,
+    /// Also download the media of quoted tweets, following quotes of quotes up to N levels
+    #[clap(long, value_name = "N", default_value = "0")]
+    follow_quote_depth: usize,
+    /// DownloadDB holding the raw json that quoted tweets are read from
+    #[clap(short = 'd', long, default_value = "dl.sqlite", value_hint = ValueHint::FilePath)]
+    download_db: PathBuf,
+    /// Write the tasks as an aria2 input file instead of downloading them. Checksums and
+    /// failures are not recorded for what aria2 downloads
+    #[clap(long, value_hint = ValueHint::FilePath)]
+    export_aria2: Option<PathBuf>,
+    /// Put all media directly in the destination as `author_tweetid_no.ext` instead of
+    /// one folder per author
+    #[clap(long, action)]
+    flat: bool,
+    /// Give up a media after this many failed downloads
+    #[clap(long, value_name = "N", default_value = "5", value_parser = clap::value_parser!(u32).range(1..))]
+    max_attempts: u32,
+    /// Seconds to wait before retrying a failed media, doubled after every failure
+    #[clap(long, value_name = "SECS", default_value = "5")]
+    retry_backoff: u64,
+    /// List the destination once and look downloaded media up in it, instead of checking every
+    /// media file on its own. Much faster on network filesystems, at the cost of memory
+    #[clap(long, action)]
+    index_existing: bool,
+    /// Skip media narrower than this many pixels
+    #[clap(long, value_name = "PX", default_value = "0")]
+    min_width: u64,
+    /// Skip media shorter than this many pixels
+    #[clap(long, value_name = "PX", default_value = "0")]
+    min_height: u64,
+    /// Download media whose size is unknown, set to false to skip them
+    #[clap(long, action = clap::ArgAction::Set, default_value = "true")]
+    include_unknown_size: bool,
+    /// More logs, -vv for trace
+    #[clap(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+    /// Only log warnings and errors
+    #[clap(short, long, action)]
+    quiet: bool,
+    #[clap(flatten)]
+    db_tuning: DBTuning,
 }
 
 fn main() {
-    println!("ShiroTweets version {}", env!("CARGO_PKG_VERSION"));
-
     let args: Args = Args::parse();
 
-    if !args.tweet_db.exists() || !args.tweet_db.is_file() {
+    env_logger::builder()
+        .format(|buf, record| {
+            writeln!(
+                buf,
+                "[{}][{}] {}",
+                chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
+                record.level(),
+                record.args()
+            )
+        })
+        .filter_module(
+            "shirotweet_downloader",
+            utils::log_level(LevelFilter::Info, args.verbose, args.quiet),
+        )
+        .init();
+    info!("ShiroTweets version {}", env!("CARGO_PKG_VERSION"));
+
+    if let Some(retry_file) = &args.retry_file {
+        if !retry_file.is_file() {
+            Args::command()
+                .error(
+                    clap::ErrorKind::ArgumentConflict,
+                    format!("Retry file `{}` not exists.", retry_file.display()),
+                )
+                .exit();
+        }
+    } else if !args.tweet_db.exists() || !args.tweet_db.is_file() {
         Args::command()
             .error(
                 clap::ErrorKind::ArgumentConflict,
@@ -225,20 +889,64 @@ fn main() {
             )
             .exit();
     }
-    if args.dest_dir.exists() && !args.dest_dir.is_dir() {
+    let namespace = if args.namespace_from_db {
+        args.tweet_db.file_stem().map(|v| v.to_string_lossy().to_string())
+    } else {
+        args.namespace.clone()
+    };
+    let dest_dir = args
+        .dest_dir
+        .clone()
+        .unwrap_or_else(|| utils::default_media_dir(&args.tweet_db));
+    let dest_dir = if let Some(namespace) = namespace {
+        dest_dir.join(sanitize_path_component(&namespace))
+    } else {
+        dest_dir
+    };
+    if dest_dir.exists() && !dest_dir.is_dir() {
         Args::command()
             .error(
                 clap::ErrorKind::ArgumentConflict,
                 format!(
                     "Download destionation dir `{}` exists but not a dir.",
-                    args.dest_dir.display()
+                    dest_dir.display()
+                ),
+            )
+            .exit();
+    }
+
+    if args.export_aria2.is_some() && args.archive_format != ArchiveFormat::None {
+        Args::command()
+            .error(
+                clap::ErrorKind::ArgumentConflict,
+                "--export-aria2 can't pack archives, use --archive-format none.",
+            )
+            .exit();
+    }
+
+    if args.flat && args.archive_format != ArchiveFormat::None {
+        Args::command()
+            .error(
+                clap::ErrorKind::ArgumentConflict,
+                "Archives are packed per author, --flat needs --archive-format none.",
+            )
+            .exit();
+    }
+
+    if args.follow_quote_depth > 0 && !args.download_db.is_file() {
+        Args::command()
+            .error(
+                clap::ErrorKind::ArgumentConflict,
+                format!(
+                    "DownloadDB file `{}` not exists.",
+                    args.download_db.display()
                 ),
             )
             .exit();
     }
 
     // run_dl_db_parser("./dl.sqlite");
-    if let Err(e) = run_downloader(args.tweet_db, args.dest_dir) {
+    if let Err(e) = run_downloader(args, dest_dir) {
         panic!("Error happen when run downloader: {}", e);
     }
 }
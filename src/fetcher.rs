@@ -1,19 +1,27 @@
 #![allow(dead_code, unused)]
+#![recursion_limit = "256"]
 
+use std::collections::{HashMap, HashSet};
 use std::io;
 use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use anyhow::Result;
-use clap::{CommandFactory, Parser, ValueHint};
-use log::{error, info, LevelFilter, trace};
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser, ValueHint};
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use log::{debug, error, info, warn, LevelFilter, trace};
 use rayon::prelude::*;
 use rpassword::read_password;
-
-use crate::tweet_db::{Media, ThreadInfo, Tweet, TweetDB, TweetFailReason};
-use crate::tweet_fetcher::{TweetDownloadDB, TweetFetcher};
-use crate::tweet_parser::TweetItem;
+use serde::{Deserialize, Serialize};
+
+use crate::tweet_db::{
+    DBTuning, EditVersion, FetchedVia, Media, ReplyInfo, ThreadInfo, Tweet, TweetBatch, TweetDB,
+    TweetFailReason, TweetStore, User,
+};
+use crate::tweet_fetcher::{RateLimitConfig, TweetDownloadDB, TweetFetcher};
+use crate::tweet_parser::{TombstoneMatchers, TweetItem};
 use crate::utils::{Error, extract_twitter_url, read_url_list};
 
 mod tweet_db;
@@ -29,38 +37,183 @@ mod utils;
    thread: https://twitter.com/onlyyougts/status/1531582206900064256
 */
 
-fn run_url_downloader<P: AsRef<Path>>(
-    url_list_path: P,
-    dl_db_file_path: P,
-    tw_db_file_path: P,
+/// Only fetch tweets newer than this, twitter ids grow with time.
+#[derive(Debug, Clone, Copy)]
+enum SinceId {
+    Id(u64),
+    /// The newest tweet of the same author in TweetDB
+    Auto,
+}
+
+fn run_url_downloader(
+    args: Args,
     login_creds: (Option<String>, Option<String>, Option<String>),
-    no_login: bool,
-    manual_login: bool,
-    no_headless: bool,
-    must_login: bool,
-    chrome_data_dir: PathBuf,
-    chrome_data_dir_login: PathBuf
+    rate_limits: (RateLimitConfig, RateLimitConfig),
+    since_id: Option<SinceId>,
+    run_config: serde_json::Value,
 ) -> Result<()> {
-    let unlogin_fetcher = if must_login {
+    let Args {
+        url_list: url_list_path,
+        download_db: dl_db_file_path,
+        tweet_db: tw_db_file_path,
+        no_login,
+        manual_login,
+        no_headless,
+        login_only,
+        chrome_data_dir,
+        chrome_data_dir_login,
+        chrome_arg: chrome_args,
+        debug_browser,
+        login_step_timeout,
+        login_timeout,
+        body_fetch_retries,
+        body_fetch_delay,
+        skip_existing,
+        raw_only,
+        expand_threads,
+        min_video_bitrate,
+        tui,
+        include_replies,
+        include_card_media,
+        keep_order,
+        priority_file,
+        max_tweets,
+        no_media_out,
+        fail_fast,
+        commit_batch,
+        db_tuning,
+        ..
+    } = args;
+    let login_timeouts = (
+        Duration::from_secs(login_step_timeout),
+        Duration::from_secs(login_timeout),
+    );
+    let body_fetch_retry = (body_fetch_retries, Duration::from_millis(body_fetch_delay));
+    let max_tweets = max_tweets.map(|v| v as usize);
+    let started_at = chrono::Local::now().timestamp();
+    let is_tw_db_existed = tw_db_file_path.exists();
+
+    let urls = read_url_list(&url_list_path, keep_order)?;
+    let urls = if let Some(priority_file) = priority_file {
+        let priority = read_url_list(priority_file, true)?;
+        info!("{} priority urls will be fetched first.", priority.len());
+        let seen = priority.iter().cloned().collect::<HashSet<String>>();
+        priority
+            .into_iter()
+            .chain(urls.into_iter().filter(|url| !seen.contains(url)))
+            .collect()
+    } else {
+        urls
+    };
+    if urls.is_empty() {
+        error!("No valid tweet url in url list, nothing to do.");
+        return Ok(());
+    }
+    // raw only mode keeps everything in download db and never touches TweetDB
+    let db: Option<Box<dyn TweetStore>> = if raw_only {
+        info!("Raw only mode, skip parsing into TweetDB.");
+        None
+    } else {
+        Some(Box::new(TweetDB::with_tuning(
+            tw_db_file_path.as_ref(),
+            db_tuning,
+        )?))
+    };
+    let dldb = TweetDownloadDB::with_tuning(dl_db_file_path, db_tuning);
+
+    let urls = if let Some(since_id) = since_id {
+        let latest = match since_id {
+            SinceId::Id(_) => HashMap::new(),
+            SinceId::Auto => db.as_ref().unwrap().latest_tweet_ids()?,
+        };
+        let before = urls.len();
+        let urls = urls
+            .into_iter()
+            .filter(|url| {
+                let (author, id) = extract_twitter_url(url).unwrap();
+                let cutoff = match since_id {
+                    SinceId::Id(v) => Some(v),
+                    SinceId::Auto => latest.get(&author.to_lowercase()).copied(),
+                };
+                cutoff.is_none_or(|cutoff| id > cutoff)
+            })
+            .collect::<Vec<String>>();
+        info!(
+            "Skip {} urls not newer than --since-id.",
+            before - urls.len()
+        );
+        urls
+    } else {
+        urls
+    };
+
+    let urls = if let (Some(db), true) = (&db, is_tw_db_existed && skip_existing) {
+        info!("TweetDB is already existed. Remove item that already in db.");
+        let urls = urls
+            .into_par_iter()
+            .filter(|p| {
+                let id = extract_twitter_url(p).unwrap().1;
+                !db.is_exist(id)
+            })
+            .collect::<Vec<String>>();
+        urls
+    } else {
+        urls
+    };
+    let urls = match max_tweets {
+        Some(max_tweets) if urls.len() > max_tweets => {
+            info!(
+                "Only fetch the first {} of {} urls, --max-tweets.",
+                max_tweets,
+                urls.len()
+            );
+            urls.into_iter().take(max_tweets).collect()
+        }
+        _ => urls,
+    };
+    let total_len = urls.len();
+    info!("{} to be downloaded.", total_len);
+    if urls.is_empty() {
+        info!("Every tweet in url list is already in TweetDB, nothing to do.");
+        return Ok(());
+    }
+
+    let unlogin_fetcher = if login_only {
+        info!("Login only, skip un-login fetcher.");
         None
     } else {
         info!("Setup un-login fetcher.");
-        Some(TweetFetcher::new(
+        let mut fetcher = TweetFetcher::new(
             // "D:\\Projects\\shirotweets\\chrome-data",
             chrome_data_dir,
             !no_headless,
-        )?)
+            &chrome_args,
+        )?;
+        fetcher.set_expand_threads(expand_threads);
+        fetcher.set_debug_browser(debug_browser);
+        fetcher.set_body_fetch_retry(body_fetch_retry.0, body_fetch_retry.1);
+        match fetcher.acquire_guest_token() {
+            Ok(Some(token)) => debug!("Got guest token {}", token),
+            Ok(None) => warn!("No guest token given, un-login fetcher may miss public tweets."),
+            Err(e) => warn!("Failed to acquire guest token: {}", e),
+        }
+        Some(fetcher)
     };
 
     let logged_in_fetcher = if no_login {
         None
     } else {
         info!("Setup logged in fetcher");
-        let fetcher = TweetFetcher::new(
+        let mut fetcher = TweetFetcher::new(
             // "D:\\Projects\\shirotweets\\chrome-data-logined",
             chrome_data_dir_login,
             !no_headless,
+            &chrome_args,
         )?;
+        fetcher.set_login_timeout(login_timeouts.0, login_timeouts.1);
+        fetcher.set_expand_threads(expand_threads);
+        fetcher.set_debug_browser(debug_browser);
+        fetcher.set_body_fetch_retry(body_fetch_retry.0, body_fetch_retry.1);
         if let Some(username) = fetcher.get_username()? {
             info!("Alread logged in as user `{}`", username);
         } else {
@@ -120,31 +273,6 @@ fn run_url_downloader<P: AsRef<Path>>(
         Some(fetcher)
     };
 
-    let is_tw_db_existed = if tw_db_file_path.as_ref().exists() {
-        true
-    } else {
-        false
-    };
-
-    let urls = read_url_list(url_list_path)?;
-    let db = TweetDB::new(tw_db_file_path.as_ref())?;
-    let dldb = TweetDownloadDB::new(dl_db_file_path);
-
-    let urls = if is_tw_db_existed {
-        info!("TweetDB is already existed. Remove item that already in db.");
-        let urls = urls
-            .into_par_iter()
-            .filter(|p| {
-                let id = extract_twitter_url(p).unwrap().1;
-                !db.is_exist(id)
-            })
-            .collect::<Vec<String>>();
-        urls
-    } else {
-        urls
-    };
-    let total_len = urls.len();
-    info!("{} to be downloaded.", total_len);
 
     let remaining = Arc::new(Mutex::new(Vec::new()));
 
@@ -154,9 +282,41 @@ fn run_url_downloader<P: AsRef<Path>>(
     let restricted_count = Arc::new(Mutex::new(0));
     let deleted_count = Arc::new(Mutex::new(0));
 
-    let tweet_without_media = Arc::new(Mutex::new(Vec::new()));
+    // (url, content)
+    let tweet_without_media = Arc::new(Mutex::new(Vec::<(String, String)>::new()));
+
+    let dashboard = if tui {
+        let multi = MultiProgress::new();
+        let fetch_bar = multi.add(ProgressBar::new(total_len as u64));
+        fetch_bar.set_style(
+            ProgressStyle::default_bar()
+                .template(
+                    "{spinner}[{elapsed_precise}][{eta}] {wide_bar:.cyan/blue} [{pos}/{len}] {prefix}\n{wide_msg}",
+                )?
+                .progress_chars("##-"),
+        );
+        let status_bar = multi.add(ProgressBar::new_spinner());
+        status_bar.set_style(ProgressStyle::default_spinner().template("{wide_msg}")?);
+        fetch_bar.enable_steady_tick(Duration::from_millis(200));
+        Some((multi, fetch_bar, status_bar))
+    } else {
+        None
+    };
+    let fetch_progress = dashboard.as_ref().map(|(_, fetch_bar, _)| fetch_bar);
 
     let status_printer = || {
+        if let Some((_, _, status_bar)) = &dashboard {
+            status_bar.set_message(format!(
+                "Success: {} | Remaining: {} | Suspended: {} | Not existed: {} | Deleted: {} | Restricted: {}",
+                success_count.lock().unwrap(),
+                remaining.lock().unwrap().len(),
+                account_suspended_count.lock().unwrap(),
+                account_not_existed_count.lock().unwrap(),
+                deleted_count.lock().unwrap(),
+                restricted_count.lock().unwrap()
+            ));
+            return;
+        }
         info!("-*-*-*-*-*-*-*-*-*-*-*-*-*-*-*-*-*-*-*-*-*-*-*-*-*-*-*-*-*-");
         info!("Success: {}", success_count.lock().unwrap());
         info!("Remaining: {}", remaining.lock().unwrap().len());
@@ -173,28 +333,36 @@ fn run_url_downloader<P: AsRef<Path>>(
         info!("-*-*-*-*-*-*-*-*-*-*-*-*-*-*-*-*-*-*-*-*-*-*-*-*-*-*-*-*-*-");
     };
 
-    let processor = |url: &str, retry_restricted: bool| {
+    let batch = Mutex::new(TweetBatch::default());
+    let flush = || -> Result<()> {
+        let mut batch = batch.lock().unwrap();
+        if !batch.is_empty() {
+            db.as_ref().unwrap().insert_batch(&batch)?;
+            *batch = TweetBatch::default();
+        }
+        Ok(())
+    };
+
+    // Err only in fail fast mode, for parse errors that mean the schema drifted
+    let processor = |url: &str, retry_restricted: bool, via: FetchedVia| -> Result<()> {
+        let db = db.as_ref().unwrap();
         let id = extract_twitter_url(url).unwrap().1;
         let json: String = dldb.get_json(id).unwrap();
 
-        let tweets_result = tweet_parser::extract_all_tweets(
-            id.to_owned(),
-            &serde_json::from_str(json.as_str()).unwrap(),
-        );
+        let tweets_result = tweet_parser::parse_tweet_json(id, json.as_str());
 
         if let Ok(tweet) = tweets_result {
             trace!("Tweet process OK for url: {}", url);
             // Tweet OK
             let thread = tweet_parser::get_thread(id, &tweet);
-            let (tweets, medias, threads) = if let Some(ids) = thread {
+            let (mut tweets, mut medias, threads) = if let Some(ids) = thread {
                 let thread_tweets = ids
                     .into_iter()
                     .map(|v| tweet.get(&v).unwrap())
                     .collect::<Vec<&TweetItem>>();
                 let medias = thread_tweets
                     .iter()
-                    .map(|v| v.get_medias())
-                    .flatten()
+                    .flat_map(|v| v.get_medias(min_video_bitrate))
                     .collect::<Vec<Media>>();
                 let tweets = thread_tweets
                     .iter()
@@ -202,35 +370,106 @@ fn run_url_downloader<P: AsRef<Path>>(
                     .collect::<Vec<Tweet>>();
                 let threads = thread_tweets
                     .iter()
-                    .map(|v| v.as_thread())
-                    .filter(|p| p.is_some())
-                    .map(|v| v.unwrap())
+                    .filter_map(|v| v.as_thread())
                     .collect::<Vec<ThreadInfo>>();
                 (tweets, medias, threads)
             } else {
                 let tweet = tweet.get(&id).unwrap();
-                (vec![tweet.as_tweet()], tweet.get_medias(), vec![])
+                (vec![tweet.as_tweet()], tweet.get_medias(min_video_bitrate), vec![])
             };
 
             if medias.is_empty() {
-                tweet_without_media.lock().unwrap().push(url.to_string());
+                let content = tweets
+                    .iter()
+                    .find(|v| v.id == id)
+                    .map(|v| v.content.clone());
+                tweet_without_media
+                    .lock()
+                    .unwrap()
+                    .push((url.to_string(), content.unwrap_or_default()));
+            }
+
+            if include_replies {
+                // keep the rest of the conversation instead of throwing it away
+                let included = tweets.iter().map(|v| v.id).collect::<HashSet<u64>>();
+                tweet
+                    .iter()
+                    .filter(|(tid, _)| !included.contains(tid))
+                    .for_each(|(_, v)| {
+                        tweets.push(v.as_tweet());
+                        medias.extend(v.get_medias(min_video_bitrate));
+                    });
             }
 
+            if include_card_media {
+                medias.extend(
+                    tweets
+                        .iter()
+                        .filter_map(|v| tweet.get(&v.id))
+                        .filter_map(|v| v.get_card_media()),
+                );
+            }
+
+            // re-fetched tweets and threads overlapping earlier ones
+            medias.retain(|v| !db.contains_media(v.tweet_id, &v.id));
+
+            if let Some(v) = tweets.iter_mut().find(|v| v.id == id) {
+                v.url = Some(url.to_string());
+            }
+            tweets.iter_mut().for_each(|v| v.fetched_via = Some(via));
+
+            // profile snapshot of every stored author
+            let mut users = tweets
+                .iter()
+                .filter_map(|v| tweet.get(&v.id))
+                .filter_map(|v| v.as_user())
+                .collect::<Vec<User>>();
+            users.sort_by_key(|v| v.id);
+            users.dedup_by_key(|v| v.id);
+
+            // reply edges, self-threads or not
+            let replies = tweets
+                .iter()
+                .filter_map(|v| tweet.get(&v.id))
+                .filter_map(|v| v.as_reply())
+                .collect::<Vec<ReplyInfo>>();
+            let edits = tweets
+                .iter()
+                .filter_map(|v| tweet.get(&v.id))
+                .flat_map(|v| v.as_edit_history())
+                .collect::<Vec<EditVersion>>();
+
             // insert into db
-            tweets.iter().for_each(|tweet| db.insert_tweet(tweet));
-            medias.iter().for_each(|media| db.insert_media(media));
-            threads.iter().for_each(|thread| db.insert_thread(thread));
+            let mut batch = batch.lock().unwrap();
+            batch.tweets.extend(tweets);
+            batch.users.extend(users);
+            batch.medias.extend(medias);
+            batch.threads.extend(threads);
+            batch.replies.extend(replies);
+            batch.edits.extend(edits);
+            let full = batch.tweets.len() >= commit_batch;
+            drop(batch);
+            if full {
+                flush()?;
+            }
             // succeed
             *success_count.lock().unwrap() += 1;
         } else {
             let err = tweets_result.err().unwrap();
-            trace!(
-                "Tweet process FAILED for url: {}. Error: {}",
-                url,
-                err.to_string()
-            );
+            trace!("Tweet process FAILED for url: {}. Error: {}", url, err);
             // println!("Failed, because: {}", err.to_string());
             if let Some(err) = err.downcast_ref::<Error>() {
+                trace!("Tweet process error code for url {}: {}", url, err.code());
+                if fail_fast
+                    && matches!(
+                        err,
+                        Error::TweetJsonSchemaInvalid(_) | Error::Unimplemented(_)
+                    )
+                {
+                    // keep what was parsed before
+                    flush()?;
+                    return Err(anyhow::anyhow!("[{}] {} for url {}", err.code(), err, url));
+                }
                 if let Some(fail) = err.try_make_fail_reason() {
                     match fail {
                         TweetFailReason::Restricted => {
@@ -263,6 +502,7 @@ fn run_url_downloader<P: AsRef<Path>>(
                 error!("Not a known error: {}", err);
             }
         }
+        Ok(())
     };
 
     let progress_count = Arc::new(Mutex::new(0));
@@ -270,7 +510,14 @@ fn run_url_downloader<P: AsRef<Path>>(
     if let Some(fetcher) = unlogin_fetcher {
         info!("Using non-login fetcher for the first round.");
 
-        let (succeed, failed) = tweet_fetcher::fetch_url_lists_to_sqlite(&fetcher, urls, &dldb)?;
+        // the logged in pass has its own limits, so go on with it even if this one gave up
+        let (succeed, failed, _) = tweet_fetcher::fetch_url_lists_to_sqlite(
+            &fetcher,
+            urls,
+            &dldb,
+            &rate_limits.0,
+            fetch_progress,
+        )?;
         info!(
             "Non-login succeed: {}, failed: {}, expected total: {}, actual total: {}. (Succeed is not always useful...)",
             succeed.len(),
@@ -278,30 +525,38 @@ fn run_url_downloader<P: AsRef<Path>>(
             total_len,
             succeed.len() + failed.len()
         );
+        info!("Non-login fetch time: {}.", fetcher.timings());
 
         let total = succeed.len();
-        info!("Try parse and move succeed items to TweetDB.");
-        succeed.iter().for_each(|url| {
-            let mut progress_count = progress_count.lock().unwrap();
-            *progress_count += 1;
-            info!("[{}/{}] Processing {}", progress_count, total, url);
-            drop(progress_count);
-            processor(url.as_str(), true);
-        });
+        if !raw_only {
+            info!("Try parse and move succeed items to TweetDB.");
+            for url in &succeed {
+                let mut progress_count = progress_count.lock().unwrap();
+                *progress_count += 1;
+                info!("[{}/{}] Processing {}", progress_count, total, url);
+                drop(progress_count);
+                processor(url.as_str(), true, FetchedVia::Anon)?;
+                if tui {
+                    status_printer();
+                }
+            }
+            flush()?;
+        }
 
-        remaining.lock().unwrap().extend(failed.into_iter());
+        remaining.lock().unwrap().extend(failed);
         info!("Total: {}", progress_count.lock().unwrap());
         status_printer();
 
         clean = true;
     } else {
-        remaining.lock().unwrap().extend(urls.into_iter());
+        remaining.lock().unwrap().extend(urls);
     }
 
     let mut retries = 0;
+    let mut aborted = false;
 
     if let Some(logged_in_fetcher) = logged_in_fetcher {
-        while !remaining.lock().unwrap().is_empty() && retries < 5 {
+        while !remaining.lock().unwrap().is_empty() && retries < 5 && !aborted {
             trace!("Not null, retries {}", retries);
             let mut remaining = remaining.lock().unwrap();
             info!("Remaining tweets: {}", remaining.len());
@@ -319,10 +574,12 @@ fn run_url_downloader<P: AsRef<Path>>(
             }
             info!("Run fetcher");
             let total_len = remaining.len();
-            let (succeed, failed) = tweet_fetcher::fetch_url_lists_to_sqlite(
+            let (succeed, failed, gave_up) = tweet_fetcher::fetch_url_lists_to_sqlite(
                 &logged_in_fetcher,
                 remaining.clone(),
                 &dldb,
+                &rate_limits.1,
+                fetch_progress,
             )?;
 
             info!("Logged-in succeed: {}, failed: {}, expected total: {}, actual total: {}. (Succeed is not always useful...)",
@@ -333,38 +590,83 @@ fn run_url_downloader<P: AsRef<Path>>(
             );
 
             remaining.clear();
-            remaining.extend(failed.into_iter());
+            remaining.extend(failed);
             drop(remaining);
+            aborted = gave_up;
 
-            info!("Try parse and move succeed items to TweetDB.");
             *progress_count.lock().unwrap() = 0;
             let total = succeed.len();
-            succeed.iter().for_each(|url| {
-                let mut progress_count = progress_count.lock().unwrap();
-                *progress_count += 1;
-                info!("[{}/{}] Processing {}", progress_count, total, url);
-                drop(progress_count);
-                processor(url.as_str(), false);
-            });
+            if !raw_only {
+                info!("Try parse and move succeed items to TweetDB.");
+                for url in &succeed {
+                    let mut progress_count = progress_count.lock().unwrap();
+                    *progress_count += 1;
+                    info!("[{}/{}] Processing {}", progress_count, total, url);
+                    drop(progress_count);
+                    processor(url.as_str(), false, FetchedVia::Login)?;
+                    if tui {
+                        status_printer();
+                    }
+                }
+                flush()?;
+            }
             info!("Total: {}", progress_count.lock().unwrap());
             status_printer();
 
             clean = true;
         }
+        info!("Logged-in fetch time: {}.", logged_in_fetcher.timings());
+    }
+
+    if aborted {
+        let remaining = remaining.lock().unwrap();
+        let path = url_list_path.with_extension("remaining.txt");
+        let content: String = remaining.iter().map(|url| format!("{}\n", url)).collect();
+        std::fs::write(&path, content)?;
+        warn!(
+            "Stopped on rate limits, {} urls left are saved to `{}`.",
+            remaining.len(),
+            path.display()
+        );
     }
 
     // done
-    tweet_without_media.lock().unwrap().iter().for_each(|url| {
-        info!("No media tweet: {}", url);
-    });
+    tweet_without_media
+        .lock()
+        .unwrap()
+        .iter()
+        .for_each(|(url, _)| {
+            info!("No media tweet: {}", url);
+        });
+    if let Some(path) = no_media_out {
+        utils::write_no_media_list(path, &tweet_without_media.lock().unwrap())?;
+    }
+
+    let success = if raw_only {
+        total_len - remaining.lock().unwrap().len()
+    } else {
+        *success_count.lock().unwrap()
+    };
+    dldb.insert_run(
+        started_at,
+        chrono::Local::now().timestamp(),
+        total_len,
+        success,
+        total_len - success,
+        &run_config.to_string(),
+    )?;
     Ok(())
 }
 
-#[derive(Parser, Debug)]
+#[derive(Parser, Serialize, Deserialize, Debug)]
 #[clap(author, version, about, long_about = None)]
 struct Args {
     #[clap(default_value = "todo.txt", value_hint = ValueHint::FilePath)]
     url_list: PathBuf,
+    /// Read options from this toml file, keys are the long names with `_`, e.g.
+    /// `download_db = "dl.sqlite"`. Options given on the command line win
+    #[clap(short = 'c', long, value_hint = ValueHint::FilePath)]
+    config: Option<PathBuf>,
     #[clap(short = 'd', long, default_value = "dl.sqlite", value_hint = ValueHint::FilePath)]
     download_db: PathBuf,
     #[clap(short = 't', long, default_value = "tw.sqlite", value_hint = ValueHint::FilePath)]
@@ -375,21 +677,160 @@ struct Args {
     password: Option<String>,
     #[clap(long)]
     verification_username: Option<String>,
+    /// Fill the username and password not given from the twitter.com entry of ~/.netrc
     #[clap(long, action)]
+    netrc: bool,
+    /// Read credentials from this netrc file instead of ~/.netrc
+    #[clap(long, value_hint = ValueHint::FilePath)]
+    netrc_file: Option<PathBuf>,
+    /// Only fetch without login, never set up the logged in fetcher
+    #[clap(long, action, conflicts_with_all = &["login-only", "manual-login"])]
     no_login: bool,
+    /// Login by hand in the browser instead of with the given credentials
     #[clap(long, action)]
     manual_login: bool,
-    #[clap(long, action)]
-    must_login: bool,
+    /// Skip the un-login pass and fetch everything with the logged in fetcher
+    #[clap(long, alias = "must-login", action)]
+    login_only: bool,
     #[clap(long, action)]
     no_headless: bool,
     #[clap(long, default_value = "chrome-data", value_hint = ValueHint::DirPath)]
     chrome_data_dir: PathBuf,
     #[clap(long, default_value = "chrome-data-login", value_hint = ValueHint::DirPath)]
-    chrome_data_dir_login: PathBuf
+    chrome_data_dir_login: PathBuf,
+    /// Extra chrome command line flag, e.g. --chrome-arg=--no-sandbox, can be repeated
+    #[clap(long, allow_hyphen_values = true, action = clap::ArgAction::Append)]
+    chrome_arg: Vec<String>,
+    /// Log the console errors and failed requests of the page when a fetch fails, needs -v
+    #[clap(long, action)]
+    debug_browser: bool,
+    /// Seconds to wait for each login form element
+    #[clap(long, default_value = "10")]
+    login_step_timeout: u64,
+    /// Seconds to wait for the redirect to home after submitting login
+    #[clap(long, default_value = "300")]
+    login_timeout: u64,
+    /// Times to retry reading a captured response body that isn't ready yet
+    #[clap(long, default_value = "20")]
+    body_fetch_retries: u32,
+    /// Milliseconds between the response body retries, raise both on slow connections
+    #[clap(long, default_value = "500")]
+    body_fetch_delay: u64,
+    /// Milliseconds to pause after every fetched tweet
+    #[clap(long, default_value = "1000")]
+    request_delay: u64,
+    /// Take a longer break every this many tweets, 0 to never
+    #[clap(long, default_value = "100")]
+    batch_size: usize,
+    /// Seconds of the longer break
+    #[clap(long, default_value = "10")]
+    batch_sleep: u64,
+    /// Seconds to sleep on the first rate limit, later ones sleep longer
+    #[clap(long, default_value = "60")]
+    rate_limit_backoff: u64,
+    /// --request-delay for the un-login pass only
+    #[clap(long)]
+    unlogin_request_delay: Option<u64>,
+    /// --batch-size for the un-login pass only
+    #[clap(long)]
+    unlogin_batch_size: Option<usize>,
+    /// --batch-sleep for the un-login pass only
+    #[clap(long)]
+    unlogin_batch_sleep: Option<u64>,
+    /// --rate-limit-backoff for the un-login pass only
+    #[clap(long)]
+    unlogin_rate_limit_backoff: Option<u64>,
+    /// Stop after this many rate limits in a row and save the urls left next to the url list.
+    /// In the un-login pass it only moves on to the logged in one
+    #[clap(long, value_name = "K", value_parser = clap::value_parser!(u32).range(1..))]
+    max_consecutive_ratelimits: Option<u32>,
+    /// Skip urls already in TweetDB, set to false to re-fetch them
+    #[clap(long, action = clap::ArgAction::Set, default_value = "true")]
+    skip_existing: bool,
+    /// Only fetch raw json into download db, don't parse into TweetDB
+    #[clap(long, action)]
+    raw_only: bool,
+    /// Scroll to load the whole thread, slower
+    #[clap(long, action)]
+    expand_threads: bool,
+    /// Pick the smallest video variant at least this bitrate instead of the highest one
+    #[clap(long)]
+    min_video_bitrate: Option<u64>,
+    /// Show a live dashboard instead of plain logs
+    #[clap(long, action)]
+    tui: bool,
+    /// Also store every reply found in the conversation
+    #[clap(long, action)]
+    include_replies: bool,
+    /// Also store the preview image of link cards as media
+    #[clap(long, action)]
+    include_card_media: bool,
+    /// Fetch in url list order instead of sorting it, so the list can set priority
+    #[clap(long, action)]
+    keep_order: bool,
+    /// Urls in this list are fetched before the ones in url list
+    #[clap(long, value_hint = ValueHint::FilePath)]
+    priority_file: Option<PathBuf>,
+    /// Only fetch tweets with a greater id, `auto` for the newest stored tweet of each author
+    #[clap(long, value_name = "ID|auto")]
+    since_id: Option<String>,
+    /// Only fetch the first N urls left after dropping the existing and older ones
+    #[clap(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    max_tweets: Option<u64>,
+    /// Write `url<TAB>content` lines of the fetched tweets without media into this file
+    #[clap(long, value_hint = ValueHint::FilePath)]
+    no_media_out: Option<PathBuf>,
+    /// Abort on the first schema or unimplemented parse error, to catch twitter changes early
+    #[clap(long, action)]
+    fail_fast: bool,
+    /// TOML file of `[<language>]` tables with `<reason> = ["text", ...]`, replacing or adding
+    /// to the built in texts tombstones are classified by
+    #[clap(long, value_hint = ValueHint::FilePath)]
+    tombstone_matchers: Option<PathBuf>,
+    /// Store parsed tweets into TweetDB in one transaction per this many tweets
+    #[clap(long, value_name = "N", default_value = "1")]
+    commit_batch: usize,
+    /// More logs, -vv for trace
+    #[clap(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+    /// Only log warnings and errors
+    #[clap(short, long, action)]
+    quiet: bool,
+    #[clap(flatten)]
+    #[serde(flatten)]
+    db_tuning: DBTuning,
+}
+
+/// Replace the options not given on the command line with the ones in the `--config` file.
+fn apply_config_file(args: Args, matches: &ArgMatches) -> Result<Args> {
+    match &args.config {
+        Some(path) => utils::apply_config_file(
+            &Args::command(),
+            &std::env::args_os().collect::<Vec<_>>(),
+            matches,
+            path,
+        ),
+        None => Ok(args),
+    }
 }
 
 fn main() {
+    let matches = Args::command().get_matches();
+    let args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    let config = args.config.clone();
+    let args = apply_config_file(args, &matches).unwrap_or_else(|e| {
+        Args::command()
+            .error(
+                clap::ErrorKind::InvalidValue,
+                format!(
+                    "Cannot apply config file `{}`: {}",
+                    config.unwrap_or_default().display(),
+                    e
+                ),
+            )
+            .exit()
+    });
+
     env_logger::builder()
         .format(|buf, record| {
             writeln!(
@@ -400,12 +841,23 @@ fn main() {
                 record.args()
             )
         })
-        .filter_module("shirotweet_fetcher", LevelFilter::Trace)
+        .filter_module(
+            "shirotweet_fetcher",
+            utils::log_level(
+                // logs would scribble over the dashboard
+                if args.tui {
+                    LevelFilter::Warn
+                } else {
+                    LevelFilter::Info
+                },
+                args.verbose,
+                args.quiet,
+            ),
+        )
         // .filter_module("headless_chrome", LevelFilter::Debug)
         .init();
     info!("ShiroTweets version {}", env!("CARGO_PKG_VERSION"));
 
-    let args: Args = Args::parse();
     if !args.url_list.exists() || !args.url_list.is_file() {
         Args::command()
             .error(
@@ -414,20 +866,101 @@ fn main() {
             )
             .exit();
     }
+    if let Some(priority_file) = &args.priority_file {
+        if !priority_file.is_file() {
+            Args::command()
+                .error(
+                    clap::ErrorKind::ArgumentConflict,
+                    format!("Priority file `{}` not exists.", priority_file.display()),
+                )
+                .exit();
+        }
+    }
+
+    let since_id = args.since_id.as_deref().map(|v| match v {
+        "auto" => SinceId::Auto,
+        v => SinceId::Id(v.parse().unwrap_or_else(|_| {
+            Args::command()
+                .error(
+                    clap::ErrorKind::InvalidValue,
+                    format!("--since-id `{}` is neither a tweet id nor auto.", v),
+                )
+                .exit()
+        })),
+    });
+    if let (Some(SinceId::Auto), true) = (since_id, args.raw_only) {
+        Args::command()
+            .error(
+                clap::ErrorKind::ArgumentConflict,
+                "--since-id auto reads TweetDB, which --raw-only doesn't use.",
+            )
+            .exit();
+    }
+    if let Some(path) = &args.tombstone_matchers {
+        match TombstoneMatchers::load(path) {
+            Ok(matchers) => tweet_parser::set_tombstone_matchers(matchers),
+            Err(e) => Args::command()
+                .error(clap::ErrorKind::InvalidValue, e.to_string())
+                .exit(),
+        }
+    }
+
+    let (mut username, mut password) = (args.username.clone(), args.password.clone());
+    let netrc_file = args
+        .netrc_file
+        .clone()
+        .or_else(|| args.netrc.then(utils::default_netrc_path).flatten());
+    if let (Some(netrc_file), true) = (netrc_file, username.is_none() || password.is_none()) {
+        match utils::read_netrc(&netrc_file, twitter_def::NETRC_MACHINES) {
+            Ok(Some((login, netrc_password))) => {
+                info!("Using credentials from `{}`.", netrc_file.display());
+                // a password for another account is no use
+                if username.is_none() || username == login {
+                    password = password.or(netrc_password);
+                }
+                username = username.or(login);
+            }
+            Ok(None) => warn!("No twitter.com entry in `{}`.", netrc_file.display()),
+            Err(e) => warn!("Cannot read netrc `{}`: {}", netrc_file.display(), e),
+        }
+    }
 
     // run_dl_db_parser("./dl.sqlite");
 
+    // everything except the password, with the username netrc may have filled in
+    let mut run_config = serde_json::to_value(&args).unwrap();
+    let config = run_config.as_object_mut().unwrap();
+    config.remove("password");
+    config.insert("username".to_string(), serde_json::json!(username));
+
+    let rate_limit = RateLimitConfig {
+        request_delay: Duration::from_millis(args.request_delay),
+        batch_size: args.batch_size,
+        batch_sleep: Duration::from_secs(args.batch_sleep),
+        backoff: Duration::from_secs(args.rate_limit_backoff),
+        max_consecutive_ratelimits: args.max_consecutive_ratelimits,
+    };
+    let unlogin_rate_limit = RateLimitConfig {
+        request_delay: args
+            .unlogin_request_delay
+            .map_or(rate_limit.request_delay, Duration::from_millis),
+        batch_size: args.unlogin_batch_size.unwrap_or(rate_limit.batch_size),
+        batch_sleep: args
+            .unlogin_batch_sleep
+            .map_or(rate_limit.batch_sleep, Duration::from_secs),
+        backoff: args
+            .unlogin_rate_limit_backoff
+            .map_or(rate_limit.backoff, Duration::from_secs),
+        ..rate_limit
+    };
+
+    let login_creds = (username, password, args.verification_username.clone());
     if let Err(e) = run_url_downloader(
-        args.url_list,
-        args.download_db,
-        args.tweet_db,
-        (args.username, args.password, args.verification_username),
-        args.no_login,
-        args.manual_login,
-        args.no_headless,
-        args.must_login,
-        args.chrome_data_dir,
-        args.chrome_data_dir_login
+        args,
+        login_creds,
+        (unlogin_rate_limit, rate_limit),
+        since_id,
+        run_config,
     ) {
         panic!("Error happen when run url downloader: {}", e);
     }
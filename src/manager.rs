@@ -0,0 +1,688 @@
+#![allow(dead_code, unused)]
+use crate::media_archive::ArchiveFormat;
+use crate::tweet_db::{
+    DBTuning, Media, MediaChecksum, Tweet, TweetDB, TweetFailReason, TweetStore,
+};
+use crate::tweet_fetcher::TweetDownloadDB;
+use crate::tweet_parser::TombstoneMatchers;
+use anyhow::Result;
+use chrono::TimeZone;
+use clap::{CommandFactory, Parser, Subcommand, ValueHint};
+use log::{info, warn, LevelFilter};
+use rusqlite::params;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+mod media_archive;
+mod tweet_db;
+mod tweet_fetcher;
+mod tweet_parser;
+mod twitter_def;
+mod utils;
+
+fn run_merge(tweet_db: PathBuf, other: PathBuf, db_tuning: DBTuning) -> Result<()> {
+    if !other.exists() || !other.is_file() {
+        Args::command()
+            .error(
+                clap::ErrorKind::ArgumentConflict,
+                format!("TweetDB file `{}` not exists.", other.display()),
+            )
+            .exit();
+    }
+    let twdb = TweetDB::with_tuning(tweet_db.as_ref(), db_tuning)?;
+    info!(
+        "Merging `{}` into `{}`.",
+        other.display(),
+        tweet_db.display()
+    );
+    twdb.merge_from(&other)
+}
+
+fn run_prune(
+    tweet_db: PathBuf,
+    download_db: PathBuf,
+    max_age: u64,
+    db_tuning: DBTuning,
+) -> Result<()> {
+    if !download_db.is_file() {
+        Args::command()
+            .error(
+                clap::ErrorKind::ArgumentConflict,
+                format!("DownloadDB file `{}` not exists.", download_db.display()),
+            )
+            .exit();
+    }
+    let dl_db = TweetDownloadDB::with_tuning(&download_db, db_tuning);
+    let cutoff = chrono::Local::now().timestamp() - (max_age * 24 * 60 * 60) as i64;
+    info!(
+        "Pruning raw json older than {} days and already in `{}`.",
+        max_age,
+        tweet_db.display()
+    );
+    let removed = dl_db.prune(&tweet_db, cutoff)?;
+    info!(
+        "Removed {} entries from `{}`.",
+        removed,
+        download_db.display()
+    );
+    Ok(())
+}
+
+fn run_prune_orphans(tweet_db: PathBuf, db_tuning: DBTuning) -> Result<()> {
+    if !tweet_db.is_file() {
+        Args::command()
+            .error(
+                clap::ErrorKind::ArgumentConflict,
+                format!("TweetDB file `{}` not exists.", tweet_db.display()),
+            )
+            .exit();
+    }
+    let twdb = TweetDB::with_tuning(tweet_db.as_ref(), db_tuning)?;
+    let removed = twdb.prune_orphans()?;
+    for (table, count) in &removed {
+        info!("Removed {} orphan {} rows.", count, table);
+    }
+    if removed.iter().any(|v| v.1 > 0) {
+        twdb.get_db_conn().execute_batch("VACUUM;")?;
+    }
+    Ok(())
+}
+
+fn run_import_json(
+    tweet_db: PathBuf,
+    file: PathBuf,
+    download_db: Option<PathBuf>,
+    tombstone_matchers: Option<PathBuf>,
+    db_tuning: DBTuning,
+) -> Result<()> {
+    if !file.is_file() {
+        Args::command()
+            .error(
+                clap::ErrorKind::ArgumentConflict,
+                format!("Json lines file `{}` not exists.", file.display()),
+            )
+            .exit();
+    }
+    if let Some(path) = tombstone_matchers {
+        tweet_parser::set_tombstone_matchers(TombstoneMatchers::load(&path)?);
+    }
+    let twdb = TweetDB::with_tuning(tweet_db.as_ref(), db_tuning)?;
+    let dldb = download_db.map(|v| TweetDownloadDB::with_tuning(v, db_tuning));
+    let (mut imported, mut failed) = (0, 0);
+    for (no, line) in std::fs::read_to_string(&file)?.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (target, tweets) = match tweet_parser::parse_external_json(line) {
+            Ok(v) => v,
+            Err(e) => {
+                warn!("Line {}: {}", no + 1, e);
+                failed += 1;
+                continue;
+            }
+        };
+        for tweet in tweets.values() {
+            twdb.insert_tweet(&tweet.as_tweet());
+            if let Some(user) = tweet.as_user() {
+                twdb.insert_user(&user);
+            }
+            tweet
+                .get_medias(None)
+                .iter()
+                .filter(|media| !twdb.contains_media(media.tweet_id, &media.id))
+                .for_each(|media| twdb.insert_media(media));
+        }
+        // after every tweet, threads reference each other
+        tweets
+            .values()
+            .filter_map(|v| v.as_thread())
+            .for_each(|thread| twdb.insert_thread(&thread));
+        tweets
+            .values()
+            .filter_map(|v| v.as_reply())
+            .for_each(|reply| twdb.insert_reply(&reply));
+        tweets
+            .values()
+            .flat_map(|v| v.as_edit_history())
+            .for_each(|version| twdb.insert_edit_version(&version));
+        if let (Some(dldb), Some(id)) = (&dldb, target) {
+            if !dldb.is_exist(id) {
+                let url = format!(
+                    "https://twitter.com/{}/status/{}",
+                    tweets[&id].as_tweet().author,
+                    id
+                );
+                dldb.insert(id, &url, line)?;
+            }
+        }
+        imported += 1;
+    }
+    info!(
+        "Imported {} payloads from `{}`, {} failed.",
+        imported,
+        file.display(),
+        failed
+    );
+    Ok(())
+}
+
+fn run_validate_list(url_list: PathBuf) -> Result<()> {
+    if !url_list.is_file() {
+        Args::command()
+            .error(
+                clap::ErrorKind::ArgumentConflict,
+                format!("Url list file `{}` not exists.", url_list.display()),
+            )
+            .exit();
+    }
+    let content = std::fs::read_to_string(&url_list)?;
+    let mut total = 0;
+    let mut urls = HashSet::new();
+    let mut matched = 0;
+    let mut unmatched = vec![];
+    for (no, line) in content.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        total += 1;
+        if let Some(url) = utils::extract_list_url(line) {
+            matched += 1;
+            urls.insert(url);
+        } else {
+            unmatched.push((no + 1, line));
+        }
+    }
+    println!("Lines: {}", total);
+    println!("Matched: {}", matched);
+    println!("Duplicates removed: {}", matched - urls.len());
+    println!("Unique urls: {}", urls.len());
+    println!("Unmatched: {}", unmatched.len());
+    for (no, line) in unmatched {
+        println!("{:>6}: {}", no, line);
+    }
+    Ok(())
+}
+
+fn run_diff_list(
+    tweet_db: PathBuf,
+    url_list: PathBuf,
+    media_dir: Option<PathBuf>,
+    archive_format: ArchiveFormat,
+    db_tuning: DBTuning,
+) -> Result<()> {
+    if !url_list.is_file() {
+        Args::command()
+            .error(
+                clap::ErrorKind::ArgumentConflict,
+                format!("Url list file `{}` not exists.", url_list.display()),
+            )
+            .exit();
+    }
+    let urls = utils::read_url_list(&url_list, true)?;
+    let twdb = TweetDB::open_readonly(tweet_db.as_ref(), db_tuning)?;
+    let fails = twdb
+        .list_fails(&[])?
+        .into_iter()
+        .map(|v| (v.tweet_id, v.reason))
+        .collect::<HashMap<u64, TweetFailReason>>();
+    // media id => where it was downloaded to, relative to the media dir
+    let mut downloaded: HashMap<String, Vec<String>> = HashMap::new();
+    for v in twdb.get_media_checksums()? {
+        downloaded.entry(v.media_id).or_default().push(v.path);
+    }
+    // paths in the media dir, only when asked to look at the files too
+    let on_disk = if let Some(media_dir) = &media_dir {
+        let mut on_disk = HashSet::new();
+        let paths = downloaded.values().flatten();
+        if archive_format == ArchiveFormat::None {
+            on_disk.extend(paths.filter(|v| media_dir.join(v).is_file()).cloned());
+        } else {
+            let folders = paths
+                .filter_map(|v| v.split_once('/').map(|v| v.0))
+                .collect::<HashSet<&str>>();
+            for folder in folders {
+                let path = media_archive::archive_path(media_dir, folder, archive_format);
+                for name in media_archive::list_entries(&path, archive_format)? {
+                    on_disk.insert(format!("{}/{}", folder, name));
+                }
+            }
+        }
+        Some(on_disk)
+    } else {
+        None
+    };
+
+    let (mut archived, mut media_missing, mut failed, mut new) = (vec![], vec![], vec![], vec![]);
+    for url in &urls {
+        let id = utils::extract_twitter_url(url).unwrap().1;
+        if let Some(reason) = fails.get(&id) {
+            failed.push((url, reason));
+        } else if !twdb.is_exist(id) {
+            new.push(url);
+        } else if twdb.get_medias(id)?.iter().all(|m| {
+            downloaded.get(&m.id).is_some_and(|paths| {
+                on_disk
+                    .as_ref()
+                    .is_none_or(|on_disk| paths.iter().any(|v| on_disk.contains(v)))
+            })
+        }) {
+            archived.push(url);
+        } else {
+            media_missing.push(url);
+        }
+    }
+    println!("Archived: {}", archived.len());
+    for url in archived {
+        println!("    {}", url);
+    }
+    println!("Media not downloaded: {}", media_missing.len());
+    for url in media_missing {
+        println!("    {}", url);
+    }
+    println!("Failed: {}", failed.len());
+    for (url, reason) in failed {
+        println!("    {} ({})", url, reason.as_str());
+    }
+    println!("New: {}", new.len());
+    for url in new {
+        println!("    {}", url);
+    }
+    Ok(())
+}
+
+fn run_list_fails(
+    tweet_db: PathBuf,
+    reasons: Vec<TweetFailReason>,
+    db_tuning: DBTuning,
+) -> Result<()> {
+    let twdb = TweetDB::open_readonly(tweet_db.as_ref(), db_tuning)?;
+    let fails = twdb.list_fails(&reasons)?;
+    for reason in TweetFailReason::ALL {
+        let urls = fails
+            .iter()
+            .filter(|v| v.reason == *reason)
+            .map(|v| v.url.as_str())
+            .collect::<Vec<&str>>();
+        if urls.is_empty() {
+            continue;
+        }
+        println!("{} ({}):", reason.as_str(), urls.len());
+        for url in urls {
+            println!("    {}", url);
+        }
+    }
+    println!("Total: {}", fails.len());
+    Ok(())
+}
+
+fn parse_fail_reason(s: &str) -> Result<TweetFailReason, String> {
+    TweetFailReason::from_str(s).ok_or_else(|| {
+        let all = TweetFailReason::ALL
+            .iter()
+            .map(|v| v.as_str())
+            .collect::<Vec<&str>>();
+        format!("expect one of: {}", all.join(", "))
+    })
+}
+
+/// Where `--append` keeps the largest `index_seq` already exported to `output`.
+fn export_state_path(output: &Path) -> PathBuf {
+    let mut name = output.file_name().unwrap_or_default().to_os_string();
+    name.push(".state");
+    output.with_file_name(name)
+}
+
+/// A stored tweet with its media, as written by `export` and `get`.
+fn tweet_json(twdb: &TweetDB, tweet: &Tweet) -> Result<serde_json::Value> {
+    #[derive(Serialize)]
+    struct TweetJson<'a> {
+        #[serde(flatten)]
+        tweet: &'a Tweet,
+        edit_count: usize,
+        edit_history: Vec<u64>,
+        medias: Vec<Media>,
+    }
+    let edit_history = twdb.get_edit_history(tweet.id)?;
+    Ok(serde_json::to_value(TweetJson {
+        tweet,
+        edit_count: edit_history.len().saturating_sub(1),
+        edit_history,
+        medias: twdb.get_medias(tweet.id)?,
+    })?)
+}
+
+fn run_export(tweet_db: PathBuf, output: PathBuf, append: bool, db_tuning: DBTuning) -> Result<()> {
+    let to_stdout = output.as_os_str() == "-";
+    if to_stdout && append {
+        Args::command()
+            .error(
+                clap::ErrorKind::ArgumentConflict,
+                "--append needs an output file, not stdout.",
+            )
+            .exit();
+    }
+    let twdb = TweetDB::open_readonly(tweet_db.as_ref(), db_tuning)?;
+    let state_path = export_state_path(&output);
+    let since: u64 = if append && state_path.is_file() {
+        let since: u64 = std::fs::read_to_string(&state_path)?.trim().parse()?;
+        // states written before index_seq hold an index_time in seconds
+        if since < 1_000_000_000_000 {
+            since * 1_000_000
+        } else {
+            since
+        }
+    } else {
+        0
+    };
+    let conn = twdb.get_db_conn();
+    let mut stmt = conn.prepare(
+        "SELECT id, index_seq FROM tweet WHERE index_seq > ?1 ORDER BY index_seq, id;",
+    )?;
+    let ids = stmt
+        .query_map(params![since], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<rusqlite::Result<Vec<(u64, u64)>>>()?;
+    let mut file: Box<dyn Write> = if to_stdout {
+        Box::new(std::io::stdout().lock())
+    } else {
+        Box::new(
+            std::fs::OpenOptions::new()
+                .create(true)
+                .write(true)
+                .append(append)
+                .truncate(!append)
+                .open(&output)?,
+        )
+    };
+    let mut high_water = since;
+    for (id, index_seq) in ids {
+        let line = tweet_json(&twdb, &twdb.get_tweet(id)?)?;
+        writeln!(file, "{}", line)?;
+        high_water = high_water.max(index_seq);
+    }
+    file.flush()?;
+    if to_stdout {
+        info!("Exported tweets to stdout, up to {}.", high_water);
+        return Ok(());
+    }
+    std::fs::write(&state_path, high_water.to_string())?;
+    info!(
+        "Exported tweets indexed after {} into `{}`, now up to {}.",
+        since,
+        output.display(),
+        high_water
+    );
+    Ok(())
+}
+
+fn run_get(tweet_db: PathBuf, id: u64, text: bool, db_tuning: DBTuning) -> Result<()> {
+    let twdb = TweetDB::open_readonly(tweet_db.as_ref(), db_tuning)?;
+    let tweet = twdb.get_tweet(id)?;
+    let thread = twdb.get_thread_tweets(id)?;
+    if !text {
+        let mut json = tweet_json(&twdb, &tweet)?;
+        json["thread"] = thread
+            .iter()
+            .map(|v| tweet_json(&twdb, v))
+            .collect::<Result<Vec<serde_json::Value>>>()?
+            .into();
+        println!("{}", serde_json::to_string_pretty(&json)?);
+        return Ok(());
+    }
+    let print_tweet = |tweet: &Tweet| -> Result<()> {
+        let time = chrono::Local
+            .timestamp_opt(tweet.create_time as i64, 0)
+            .single()
+            .map_or_else(|| tweet.create_time.to_string(), |v| v.to_rfc3339());
+        println!("@{} at {} ({})", tweet.author, time, tweet.id);
+        for line in tweet.content.lines() {
+            println!("    {}", line);
+        }
+        for media in twdb.get_medias(tweet.id)? {
+            println!("    [{}] {}", media._type.as_str(), media.url);
+        }
+        Ok(())
+    };
+    print_tweet(&tweet)?;
+    if !thread.is_empty() {
+        println!();
+        println!("Thread ({}):", thread.len());
+        for v in &thread {
+            print_tweet(v)?;
+        }
+    }
+    Ok(())
+}
+
+fn run_verify_archive(
+    tweet_db: PathBuf,
+    dest_dir: PathBuf,
+    archive_format: ArchiveFormat,
+    db_tuning: DBTuning,
+) -> Result<()> {
+    if !tweet_db.is_file() {
+        Args::command()
+            .error(
+                clap::ErrorKind::ArgumentConflict,
+                format!("TweetDB file `{}` not exists.", tweet_db.display()),
+            )
+            .exit();
+    }
+    let twdb = TweetDB::with_tuning(tweet_db.as_ref(), db_tuning)?;
+    let checksums = twdb.get_media_checksums()?;
+    info!(
+        "Verifying {} media files in `{}`.",
+        checksums.len(),
+        dest_dir.display()
+    );
+
+    // path => digest found on disk, absent when the file is missing
+    let mut found: HashMap<String, String> = HashMap::new();
+    if archive_format == ArchiveFormat::None {
+        for v in &checksums {
+            let file = dest_dir.join(&v.path);
+            if file.is_file() {
+                found.insert(v.path.clone(), utils::checksum(std::fs::File::open(file)?)?);
+            }
+        }
+    } else {
+        let folders = checksums
+            .iter()
+            .filter_map(|v| v.path.split_once('/').map(|v| v.0))
+            .collect::<HashSet<&str>>();
+        for folder in folders {
+            let path = media_archive::archive_path(&dest_dir, folder, archive_format);
+            if !path.is_file() {
+                continue;
+            }
+            media_archive::for_each_entry(&path, archive_format, |name, reader| {
+                found.insert(format!("{}/{}", folder, name), utils::checksum(reader)?);
+                Ok(())
+            })?;
+        }
+    }
+
+    let (mut ok, mut mismatched, mut missing) = (0, vec![], vec![]);
+    for v in &checksums {
+        match found.get(&v.path) {
+            Some(digest) if digest == &v.digest => {
+                twdb.touch_media_checksum(&v.media_id, &v.algo)?;
+                ok += 1;
+            }
+            Some(_) => mismatched.push(v),
+            None => missing.push(v),
+        }
+    }
+    println!("OK: {}", ok);
+    println!("Mismatched: {}", mismatched.len());
+    for v in mismatched {
+        println!("    {} ({})", v.path, v.media_id);
+    }
+    println!("Missing: {}", missing.len());
+    for v in missing {
+        println!("    {} ({})", v.path, v.media_id);
+    }
+    Ok(())
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Merge another TweetDB into this one
+    Merge {
+        #[clap(value_hint = ValueHint::FilePath)]
+        other: PathBuf,
+    },
+    /// Import tweet payloads captured by other tools, one json per line
+    ImportJson {
+        #[clap(value_hint = ValueHint::FilePath)]
+        file: PathBuf,
+        /// Also keep whole TweetDetail or TweetResultByRestId responses in this DownloadDB
+        #[clap(short = 'd', long, value_hint = ValueHint::FilePath)]
+        download_db: Option<PathBuf>,
+        /// Classify tombstones with these matchers too, see the fetcher's --tombstone-matchers
+        #[clap(long, value_hint = ValueHint::FilePath)]
+        tombstone_matchers: Option<PathBuf>,
+    },
+    /// Report unparseable lines and duplicates of a url list without fetching anything
+    ValidateList {
+        #[clap(default_value = "todo.txt", value_hint = ValueHint::FilePath)]
+        url_list: PathBuf,
+    },
+    /// Sort the urls of a list into archived, media not downloaded, failed and new ones
+    DiffList {
+        #[clap(default_value = "todo.txt", value_hint = ValueHint::FilePath)]
+        url_list: PathBuf,
+        /// Also check the media files are still in this folder, instead of only trusting the
+        /// downloads recorded in the TweetDB
+        #[clap(long, value_hint = ValueHint::DirPath)]
+        media_dir: Option<PathBuf>,
+        #[clap(long, value_enum, default_value = "none")]
+        archive_format: ArchiveFormat,
+    },
+    /// Export tweets with their media as json lines
+    Export {
+        /// `-` writes to stdout
+        #[clap(value_hint = ValueHint::FilePath)]
+        output: PathBuf,
+        /// Only append tweets indexed since the last export into the same file
+        #[clap(long, action)]
+        append: bool,
+    },
+    /// Print one stored tweet with its media and thread as json
+    Get {
+        tweet_id: u64,
+        /// Print readable text instead of json
+        #[clap(long, action)]
+        text: bool,
+    },
+    /// List the recorded fetch failures grouped by reason
+    ListFails {
+        /// Only list failures of this reason, can be repeated
+        #[clap(long, value_parser = parse_fail_reason, action = clap::ArgAction::Append)]
+        reason: Vec<TweetFailReason>,
+    },
+    /// Re-hash downloaded media and report files that changed or went missing
+    VerifyArchive {
+        /// Defaults to `<db stem>_media` next to the TweetDB, like the downloader
+        #[clap(value_hint = ValueHint::DirPath)]
+        dest_dir: Option<PathBuf>,
+        #[clap(long, value_enum, default_value = "none")]
+        archive_format: ArchiveFormat,
+    },
+    /// Delete parsed raw json from the DownloadDB
+    Prune {
+        #[clap(short = 'd', long, default_value = "dl.sqlite", value_hint = ValueHint::FilePath)]
+        download_db: PathBuf,
+        /// Only delete entries fetched more than this many days ago
+        #[clap(long, value_name = "DAYS")]
+        max_age: u64,
+    },
+    /// Delete media, thread, reply and edit history rows of tweets no longer in the TweetDB
+    PruneOrphanMedia,
+}
+
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+struct Args {
+    #[clap(short = 't', long, global = true, default_value = "tw.sqlite", value_hint = ValueHint::FilePath)]
+    tweet_db: PathBuf,
+    #[clap(subcommand)]
+    command: Command,
+    /// More logs, -vv for trace
+    #[clap(short, long, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+    /// Only log warnings and errors
+    #[clap(short, long, global = true, action)]
+    quiet: bool,
+    #[clap(flatten)]
+    db_tuning: DBTuning,
+}
+
+fn main() {
+    let args: Args = Args::parse();
+
+    env_logger::builder()
+        .format(|buf, record| {
+            writeln!(
+                buf,
+                "[{}][{}] {}",
+                chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
+                record.level(),
+                record.args()
+            )
+        })
+        .filter_module(
+            "shirotweet_manager",
+            utils::log_level(LevelFilter::Info, args.verbose, args.quiet),
+        )
+        .init();
+    info!("ShiroTweets version {}", env!("CARGO_PKG_VERSION"));
+
+    let result = match args.command {
+        Command::Merge { other } => run_merge(args.tweet_db, other, args.db_tuning),
+        Command::ImportJson {
+            file,
+            download_db,
+            tombstone_matchers,
+        } => run_import_json(
+            args.tweet_db,
+            file,
+            download_db,
+            tombstone_matchers,
+            args.db_tuning,
+        ),
+        Command::ValidateList { url_list } => run_validate_list(url_list),
+        Command::DiffList {
+            url_list,
+            media_dir,
+            archive_format,
+        } => run_diff_list(
+            args.tweet_db,
+            url_list,
+            media_dir,
+            archive_format,
+            args.db_tuning,
+        ),
+        Command::Export { output, append } => {
+            run_export(args.tweet_db, output, append, args.db_tuning)
+        }
+        Command::Get { tweet_id, text } => run_get(args.tweet_db, tweet_id, text, args.db_tuning),
+        Command::ListFails { reason } => run_list_fails(args.tweet_db, reason, args.db_tuning),
+        Command::VerifyArchive {
+            dest_dir,
+            archive_format,
+        } => {
+            let dest_dir = dest_dir.unwrap_or_else(|| utils::default_media_dir(&args.tweet_db));
+            run_verify_archive(args.tweet_db, dest_dir, archive_format, args.db_tuning)
+        }
+        Command::Prune {
+            download_db,
+            max_age,
+        } => run_prune(args.tweet_db, download_db, max_age, args.db_tuning),
+        Command::PruneOrphanMedia => run_prune_orphans(args.tweet_db, args.db_tuning),
+    };
+    if let Err(e) = result {
+        panic!("Error happen when run manager: {}", e);
+    }
+}
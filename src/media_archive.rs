@@ -0,0 +1,182 @@
+use std::collections::HashSet;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use clap::ValueEnum;
+use log::{error, info};
+
+use crate::utils::Error;
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    None,
+    Tar,
+    Zip,
+}
+
+impl ArchiveFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::None => "",
+            Self::Tar => "tar",
+            Self::Zip => "zip",
+        }
+    }
+}
+
+// Size of the two zero blocks terminating a tar archive.
+const TAR_EOF_LEN: u64 = 1024;
+
+pub fn archive_path(dest_dir: &Path, author: &str, format: ArchiveFormat) -> PathBuf {
+    dest_dir.join(format!("{}.{}", author, format.extension()))
+}
+
+/// Names of the media already stored inside an author's archive.
+pub fn list_entries(path: &Path, format: ArchiveFormat) -> Result<HashSet<String>> {
+    if !path.exists() {
+        return Ok(HashSet::new());
+    }
+    let file = File::open(path)?;
+    match format {
+        ArchiveFormat::None => Ok(HashSet::new()),
+        ArchiveFormat::Tar => {
+            let mut archive = tar::Archive::new(file);
+            let mut names = HashSet::new();
+            for entry in archive.entries()? {
+                names.insert(entry?.path()?.to_string_lossy().to_string());
+            }
+            Ok(names)
+        }
+        ArchiveFormat::Zip => {
+            let archive = zip::ZipArchive::new(file)?;
+            Ok(archive.file_names().map(|v| v.to_string()).collect())
+        }
+    }
+}
+
+/// Call `f` with the name and content of every entry in the archive at `path`.
+pub fn for_each_entry<F>(path: &Path, format: ArchiveFormat, mut f: F) -> Result<()>
+where
+    F: FnMut(&str, &mut dyn Read) -> Result<()>,
+{
+    let file = File::open(path)?;
+    match format {
+        ArchiveFormat::None => {}
+        ArchiveFormat::Tar => {
+            let mut archive = tar::Archive::new(file);
+            for entry in archive.entries()? {
+                let mut entry = entry?;
+                let name = entry.path()?.to_string_lossy().to_string();
+                f(&name, &mut entry)?;
+            }
+        }
+        ArchiveFormat::Zip => {
+            let mut archive = zip::ZipArchive::new(file)?;
+            for i in 0..archive.len() {
+                let mut entry = archive.by_index(i)?;
+                let name = entry.name().to_string();
+                f(&name, &mut entry)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Append `files` (entry name, file on disk) to the archive at `path`, creating it if needed.
+pub fn append_files(path: &Path, format: ArchiveFormat, files: &[(String, PathBuf)]) -> Result<()> {
+    let existed = path.exists();
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        // appended to in place, an existing archive must keep its entries
+        .truncate(false)
+        .open(path)?;
+    match format {
+        ArchiveFormat::None => {
+            return Err(Error::CustomError {
+                msg: "No archive format to append to.".to_string(),
+            }
+            .into())
+        }
+        ArchiveFormat::Tar => {
+            if existed {
+                // overwrite the end-of-archive marker with the new entries
+                let len = file.metadata()?.len();
+                file.seek(SeekFrom::Start(len.saturating_sub(TAR_EOF_LEN)))?;
+            }
+            let mut builder = tar::Builder::new(file);
+            for (name, src) in files {
+                builder.append_path_with_name(src, name)?;
+            }
+            builder.finish()?;
+        }
+        ArchiveFormat::Zip => {
+            let mut writer = if existed {
+                zip::ZipWriter::new_append(file)?
+            } else {
+                zip::ZipWriter::new(file)
+            };
+            // media is already compressed, store as is
+            let options = zip::write::FileOptions::default()
+                .compression_method(zip::CompressionMethod::Stored)
+                .large_file(true);
+            for (name, src) in files {
+                writer.start_file(name.as_str(), options)?;
+                std::io::copy(&mut File::open(src)?, &mut writer)?;
+            }
+            writer.finish()?;
+        }
+    }
+    Ok(())
+}
+
+/// Move every `staging_dir/<author>/<file>` into `dest_dir/<author>.<ext>`. An author that
+/// can't be packed keeps its staging folder, the others are still packed.
+pub fn pack_staging(staging_dir: &Path, dest_dir: &Path, format: ArchiveFormat) -> Result<()> {
+    if !staging_dir.exists() {
+        return Ok(());
+    }
+    let mut failed = vec![];
+    for author_dir in std::fs::read_dir(staging_dir)? {
+        let author_dir = author_dir?.path();
+        if !author_dir.is_dir() {
+            continue;
+        }
+        let author = author_dir
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .to_string();
+        let files = std::fs::read_dir(&author_dir)?
+            .filter_map(|v| v.ok().map(|v| v.path()))
+            .filter(|p| p.is_file())
+            .map(|p| (p.file_name().unwrap().to_string_lossy().to_string(), p))
+            .collect::<Vec<(String, PathBuf)>>();
+        if !files.is_empty() {
+            let path = archive_path(dest_dir, &author, format);
+            info!("Packing {} files into {}", files.len(), path.display());
+            if let Err(e) = append_files(&path, format, &files) {
+                error!("Cannot pack into {}: {}", path.display(), e);
+                failed.push(author);
+                continue;
+            }
+        }
+        std::fs::remove_dir_all(&author_dir)?;
+    }
+    if !failed.is_empty() {
+        return Err(Error::CustomError {
+            msg: format!(
+                "{} authors could not be packed ({}), their media is left in {}",
+                failed.len(),
+                failed.join(", "),
+                staging_dir.display()
+            ),
+        }
+        .into());
+    }
+    std::fs::remove_dir(staging_dir)?;
+    Ok(())
+}
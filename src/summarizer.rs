@@ -1,5 +1,5 @@
 #![allow(dead_code, unused)]
-use crate::tweet_db::TweetDB;
+use crate::tweet_db::{DBTuning, TweetDB, TweetStore};
 use crate::tweet_fetcher::TweetDownloadDB;
 use crate::utils::Error;
 use crate::utils::{extract_twitter_url, read_url_list};
@@ -17,8 +17,20 @@ mod tweet_parser;
 mod twitter_def;
 mod utils;
 
-fn run_summarizer<P: AsRef<Path>>(url_list: P, dldb_path: P, twdb_path: P) -> Result<()> {
-    let urls = read_url_list(&url_list)?;
+fn run_summarizer<P: AsRef<Path>>(
+    url_list: P,
+    dldb_path: P,
+    twdb_path: P,
+    threads: usize,
+    output_failures: Option<PathBuf>,
+    no_media_out: Option<PathBuf>,
+    db_tuning: DBTuning,
+) -> Result<()> {
+    let (url_list, dldb_path) = (url_list.as_ref(), dldb_path.as_ref());
+    let urls = read_url_list(url_list, false)?;
+    // SQLite serializes writers anyway, keep the pool small to avoid lock contention.
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build()?;
+    info!("Summarizer using {} threads.", pool.current_num_threads());
 
     let list_total_count = urls.len();
 
@@ -70,8 +82,10 @@ fn run_summarizer<P: AsRef<Path>>(url_list: P, dldb_path: P, twdb_path: P) -> Re
         info!("-*-*-*-*-*-*-*-*-*-*-*-*-*-*-*-*-*-*-*-*-*-*-*-*-*-*-*-*-*-");
     };
 
-    let dldb = TweetDownloadDB::new(&dldb_path);
-    let twdb = TweetDB::new(twdb_path.as_ref())?;
+    let dldb = TweetDownloadDB::with_tuning(dldb_path, db_tuning);
+    // only reads, a mistyped path must not leave an empty TweetDB behind
+    let twdb: Box<dyn TweetStore> =
+        Box::new(TweetDB::open_readonly(twdb_path.as_ref(), db_tuning)?);
 
     let make_existed_url =
         |urls: Vec<String>, filter: Box<dyn Fn(&&String) -> bool + Sync + Send>, warn_msg: &str| {
@@ -83,9 +97,9 @@ fn run_summarizer<P: AsRef<Path>>(url_list: P, dldb_path: P, twdb_path: P) -> Re
             if !not_in_dldb.is_empty() {
                 warn!(
                     "Count of Tweets in url_list `{}` but not in {} `{}` is {}.",
-                    url_list.as_ref().display(),
+                    url_list.display(),
                     warn_msg,
-                    dldb_path.as_ref().display(),
+                    dldb_path.display(),
                     not_in_dldb.len()
                 );
                 let not_in_dldb = not_in_dldb
@@ -93,7 +107,7 @@ fn run_summarizer<P: AsRef<Path>>(url_list: P, dldb_path: P, twdb_path: P) -> Re
                     .map(|v| v.clone())
                     .collect::<Vec<String>>();
                 urls.into_par_iter()
-                    .filter(|p| not_in_dldb.contains(&p))
+                    .filter(|p| not_in_dldb.contains(p))
                     .collect()
             } else {
                 info!("Good, every tweet in url_list is inside {}.", warn_msg);
@@ -101,52 +115,85 @@ fn run_summarizer<P: AsRef<Path>>(url_list: P, dldb_path: P, twdb_path: P) -> Re
             }
         };
 
-    let urls = make_existed_url(
-        urls,
-        Box::new(|p: &&String| !dldb.is_exist(extract_twitter_url(p).unwrap().1)),
-        "Download DB",
-    );
-    let urls = make_existed_url(
-        urls,
-        Box::new(|p: &&String| !twdb.is_exist(extract_twitter_url(p).unwrap().1)),
-        "Tweet DB",
-    );
-
-    urls.into_par_iter().for_each(|url| {
-        let id = extract_twitter_url(&url).unwrap().1;
-        let tweet = twdb.get_tweet(id);
-        if let Ok(tweet) = tweet {
-            *success_count.lock().unwrap() += 1;
-            let medias = twdb.get_medias(id).unwrap();
-            if medias.is_empty() {
-                tweet_without_media
-                    .lock()
-                    .unwrap()
-                    .push((url, tweet.content));
+    pool.install(|| {
+        let urls = make_existed_url(
+            urls,
+            Box::new(|p: &&String| !dldb.is_exist(extract_twitter_url(p).unwrap().1)),
+            "Download DB",
+        );
+        let urls = make_existed_url(
+            urls,
+            Box::new(|p: &&String| !twdb.is_exist(extract_twitter_url(p).unwrap().1)),
+            "Tweet DB",
+        );
+
+        urls.into_par_iter().for_each(|url| {
+            let id = extract_twitter_url(&url).unwrap().1;
+            let tweet = twdb.get_tweet(id);
+            if let Ok(tweet) = tweet {
+                *success_count.lock().unwrap() += 1;
+                let medias = twdb.get_medias(id).unwrap();
+                if medias.is_empty() {
+                    tweet_without_media
+                        .lock()
+                        .unwrap()
+                        .push((url, tweet.content));
+                } else {
+                    *medias_count.lock().unwrap() += medias.len();
+                }
             } else {
-                *medias_count.lock().unwrap() += medias.len();
-            }
-        } else {
-            let err = tweet.unwrap_err();
-            let err_str = err.to_string();
-            if let Ok(err) = err.downcast::<Error>() {
-                match err {
-                    Error::TweetNotExists => *deleted_count.lock().unwrap() += 1,
-                    Error::TweetRestricted => *restricted_count.lock().unwrap() += 1,
-                    Error::TwitterAccountSuspended => *account_suspended_count.lock().unwrap() += 1,
-                    Error::TwitterAccountNotExisted => {
-                        *account_not_existed_count.lock().unwrap() += 1
+                let err = tweet.unwrap_err();
+                let err_str = err.to_string();
+                let fetch_fail = dldb
+                    .get_fetch_fail(id)
+                    .ok()
+                    .flatten()
+                    .map_or("".to_string(), |(code, detail)| {
+                        format!(" (last fetch fail: [{}] {})", code, detail)
+                    });
+                if let Ok(err) = err.downcast::<Error>() {
+                    match err {
+                        Error::TweetNotExists => *deleted_count.lock().unwrap() += 1,
+                        Error::TweetRestricted => *restricted_count.lock().unwrap() += 1,
+                        Error::TwitterAccountSuspended => {
+                            *account_suspended_count.lock().unwrap() += 1
+                        }
+                        Error::TwitterAccountNotExisted => {
+                            *account_not_existed_count.lock().unwrap() += 1
+                        }
+                        _ => other_failed
+                            .lock()
+                            .unwrap()
+                            .push((url, format!("[{}] {}{}", err.code(), err, fetch_fail))),
                     }
-                    _ => other_failed.lock().unwrap().push((url, err.to_string())),
+                } else {
+                    other_failed
+                        .lock()
+                        .unwrap()
+                        .push((url, err_str + &fetch_fail));
                 }
-            } else {
-                other_failed.lock().unwrap().push((url, err_str));
             }
-        }
+        });
     });
 
     status_printer();
 
+    if let Some(output_dir) = output_failures {
+        std::fs::create_dir_all(&output_dir)?;
+        let write_urls = |filename: &str, list: &Vec<(String, String)>| -> Result<()> {
+            let path = output_dir.join(filename);
+            let content: String = list.iter().map(|(url, _)| format!("{}\n", url)).collect();
+            std::fs::write(&path, content)?;
+            info!("{} urls written to {}", list.len(), path.display());
+            Ok(())
+        };
+        write_urls("no_media.txt", &tweet_without_media.lock().unwrap())?;
+        write_urls("failed.txt", &other_failed.lock().unwrap())?;
+    }
+    if let Some(path) = no_media_out {
+        utils::write_no_media_list(path, &tweet_without_media.lock().unwrap())?;
+    }
+
     Ok(())
 }
 
@@ -159,9 +206,27 @@ struct Args {
     download_db: PathBuf,
     #[clap(short = 't', long, default_value = "tw.sqlite", value_hint = ValueHint::FilePath)]
     tweet_db: PathBuf,
+    #[clap(long, default_value = "4")]
+    threads: usize,
+    /// Write no-media and failed url lists into this dir for a retry pass
+    #[clap(long, value_hint = ValueHint::DirPath)]
+    output_failures: Option<PathBuf>,
+    /// Write `url<TAB>content` lines of the tweets without media into this file
+    #[clap(long, value_hint = ValueHint::FilePath)]
+    no_media_out: Option<PathBuf>,
+    /// More logs, -vv for trace
+    #[clap(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+    /// Only log warnings and errors
+    #[clap(short, long, action)]
+    quiet: bool,
+    #[clap(flatten)]
+    db_tuning: DBTuning,
 }
 
 fn main() {
+    let args: Args = Args::parse();
+
     env_logger::builder()
         .format(|buf, record| {
             writeln!(
@@ -172,13 +237,14 @@ fn main() {
                 record.args()
             )
         })
-        .filter_module("shirotweet_summarizer", LevelFilter::Info)
+        .filter_module(
+            "shirotweet_summarizer",
+            utils::log_level(LevelFilter::Info, args.verbose, args.quiet),
+        )
         // .filter_module("headless_chrome", LevelFilter::Debug)
         .init();
     info!("ShiroTweets version {}", env!("CARGO_PKG_VERSION"));
 
-    let args: Args = Args::parse();
-
     let file_checker = |p: &PathBuf, file_usage| {
         if !p.exists() || !p.is_file() {
             Args::command()
@@ -195,7 +261,15 @@ fn main() {
     file_checker(&args.download_db, "DownloadDB file");
 
     // run_dl_db_parser("./dl.sqlite");
-    if let Err(e) = run_summarizer(args.url_list, args.download_db, args.tweet_db) {
+    if let Err(e) = run_summarizer(
+        args.url_list,
+        args.download_db,
+        args.tweet_db,
+        args.threads,
+        args.output_failures,
+        args.no_media_out,
+        args.db_tuning,
+    ) {
         panic!("Error happen when run summaryizer: {}", e);
     }
 }
@@ -2,65 +2,337 @@ use crate::utils::extract_twitter_url;
 use crate::utils::Error;
 use crate::utils::Error::{TweetRestricted, TwitterAccountNotExisted, TwitterAccountSuspended};
 use anyhow::Result;
-use log::error;
+use log::{error, info, warn};
 use r2d2::PooledConnection;
 use r2d2_sqlite::SqliteConnectionManager;
-use rusqlite::params;
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSqlOutput, ValueRef};
+use rusqlite::{params, Connection, OpenFlags, ToSql};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
 use std::path::Path;
 use std::time::Duration;
 
-#[derive(Debug)]
+/// Per-connection SQLite settings, worth raising for multi-GB archives.
+#[derive(clap::Args, Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct DBTuning {
+    /// SQLite page cache size per connection
+    #[clap(
+        long,
+        global = true,
+        default_value = "64",
+        value_name = "MiB",
+        help_heading = "SQLITE TUNING"
+    )]
+    pub sqlite_cache_size: u64,
+    /// SQLite memory-mapped I/O size, 0 to disable
+    #[clap(
+        long,
+        global = true,
+        default_value = "256",
+        value_name = "MiB",
+        help_heading = "SQLITE TUNING"
+    )]
+    pub sqlite_mmap_size: u64,
+}
+
+impl Default for DBTuning {
+    fn default() -> Self {
+        Self {
+            sqlite_cache_size: 64,
+            sqlite_mmap_size: 256,
+        }
+    }
+}
+
+impl DBTuning {
+    pub fn manager<P: AsRef<Path>>(&self, db_path: P) -> SqliteConnectionManager {
+        // negative cache_size is in KiB instead of pages
+        let pragmas = format!(
+            "PRAGMA cache_size = -{}; PRAGMA mmap_size = {};",
+            self.sqlite_cache_size * 1024,
+            self.sqlite_mmap_size * 1024 * 1024
+        );
+        SqliteConnectionManager::file(db_path).with_init(move |c| c.execute_batch(&pragmas))
+    }
+}
+
+/// Serializes with the field names as they are, which are part of the export format.
+#[derive(Serialize, Deserialize, Debug)]
 pub struct ThreadInfo {
     pub tweet_id: u64,
     pub thread_id: u64,
     pub reply_to: u64,
 }
 
+/// A tweet replying to another one, whether or not it is part of a self-thread.
 #[derive(Debug)]
+pub struct ReplyInfo {
+    pub tweet_id: u64,
+    pub in_reply_to_id: u64,
+    pub in_reply_to_screen_name: Option<String>,
+}
+
+/// One version of an edited tweet, `version_no` counts from 1 for the original.
+#[derive(Debug)]
+pub struct EditVersion {
+    pub tweet_id: u64,
+    pub version_no: u32,
+    pub version_tweet_id: u64,
+}
+
+/// Serializes with the field names as they are, which are part of the export format.
+/// Optional fields come out as `null` and may be left out when deserializing.
+#[derive(Serialize, Deserialize, Debug)]
 pub struct Tweet {
     pub id: u64,
+    /// Screen name
     pub author: String,
     pub content: String,
-    pub create_time: u64, // sec timestamp
+    /// Seconds since epoch
+    pub create_time: u64,
+    pub view_count: Option<u64>,
+    pub conversation_id: Option<u64>,
+    pub in_reply_to_status_id: Option<u64>,
+    pub in_reply_to_screen_name: Option<String>,
+    pub lang: Option<String>,
+    /// The url it was fetched from, None for tweets that came along in a thread
+    pub url: Option<String>,
+    /// The fetcher pass that got it, None for imported tweets and ones stored before it was kept
+    pub fetched_via: Option<FetchedVia>,
+    /// Seconds since epoch, filled by the db, None until stored
+    pub index_time: Option<u64>,
+    pub fetch_time: Option<u64>,
 }
 
 #[derive(Debug)]
+pub struct User {
+    pub id: u64,
+    pub screen_name: String,
+    pub name: String,
+    pub bio: String,
+    pub followers: u64,
+    pub avatar_url: String,
+}
+
+/// A media download that gave up, `path` is `folder/filename` under the download destination.
+#[derive(Debug)]
+pub struct MediaFail {
+    pub path: String,
+    pub url: String,
+    pub media_id: Option<String>,
+    pub error: String,
+}
+
+/// Rows written together in one transaction by `TweetStore::insert_batch`.
+#[derive(Default)]
+pub struct TweetBatch {
+    pub tweets: Vec<Tweet>,
+    pub users: Vec<User>,
+    pub medias: Vec<Media>,
+    pub threads: Vec<ThreadInfo>,
+    pub replies: Vec<ReplyInfo>,
+    pub edits: Vec<EditVersion>,
+}
+
+impl TweetBatch {
+    pub fn is_empty(&self) -> bool {
+        self.tweets.is_empty()
+            && self.users.is_empty()
+            && self.medias.is_empty()
+            && self.threads.is_empty()
+            && self.replies.is_empty()
+            && self.edits.is_empty()
+    }
+}
+
+/// Digest of a downloaded media file, `path` is relative to the download destination.
+#[derive(Debug)]
+pub struct MediaChecksum {
+    pub media_id: String,
+    pub path: String,
+    pub algo: String,
+    pub digest: String,
+    pub verified_at: u64,
+}
+
+#[derive(Debug)]
+pub struct TweetFail {
+    pub tweet_id: u64,
+    pub url: String,
+    pub reason: TweetFailReason,
+}
+
+/// `type` of a media entity, stored as twitter's own string.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MediaType {
+    Photo,
+    Video,
+    AnimatedGif,
+    // preview image of a link card, not a media entity
+    CardImage,
+    // anything twitter adds later
+    Other(String),
+}
+
+impl MediaType {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::Photo => "photo",
+            Self::Video => "video",
+            Self::AnimatedGif => "animated_gif",
+            Self::CardImage => "card_image",
+            Self::Other(s) => s.as_str(),
+        }
+    }
+
+    /// Played from a `video_info` variant instead of the image url.
+    pub fn is_video(&self) -> bool {
+        matches!(self, Self::Video | Self::AnimatedGif)
+    }
+}
+
+impl From<&str> for MediaType {
+    fn from(s: &str) -> Self {
+        match s {
+            "photo" => Self::Photo,
+            "video" => Self::Video,
+            "animated_gif" => Self::AnimatedGif,
+            "card_image" => Self::CardImage,
+            s => Self::Other(s.to_string()),
+        }
+    }
+}
+
+impl ToSql for MediaType {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(self.as_str().into())
+    }
+}
+
+impl FromSql for MediaType {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        value.as_str().map(Self::from)
+    }
+}
+
+impl Serialize for MediaType {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for MediaType {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        Ok(String::deserialize(deserializer)?.as_str().into())
+    }
+}
+
+/// Which fetcher pass got a tweet, tells how much of an archive depends on a logged in session.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FetchedVia {
+    Anon,
+    Login,
+}
+
+impl FetchedVia {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Anon => "anon",
+            Self::Login => "login",
+        }
+    }
+}
+
+impl ToSql for FetchedVia {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(self.as_str().into())
+    }
+}
+
+impl FromSql for FetchedVia {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "anon" => Ok(Self::Anon),
+            "login" => Ok(Self::Login),
+            _ => Err(FromSqlError::InvalidType),
+        }
+    }
+}
+
+/// Serializes with the field names as they are, which are part of the export format, except
+/// `_type` which is `type`.
+#[derive(Serialize, Deserialize, Debug)]
 pub struct Media {
     pub id: String,
     pub tweet_id: u64,
     pub url: String,
     pub width: u64,
     pub height: u64,
+    /// Position in the tweet, from 1
     pub no: i32,
-    pub _type: String,
+    #[serde(rename = "type")]
+    pub _type: MediaType,
+    /// Poster image of videos and gifs, `url` is the playable variant for those
+    pub thumbnail_url: Option<String>,
 }
 
-pub enum TweetFailReason {
-    Restricted,
-    Deleted,
-    AccountSuspended,
-    AccountNotExisted,
-}
+// Single source of the reason strings, used by the conversions and the `fail.type` CHECK.
+macro_rules! fail_reasons {
+    ($($variant:ident => $text:literal),+ $(,)?) => {
+        #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+        pub enum TweetFailReason {
+            $($variant),+
+        }
 
-impl ToString for TweetFailReason {
-    fn to_string(&self) -> String {
-        match self {
-            Self::Restricted => "restricted",
-            Self::Deleted => "deleted",
-            Self::AccountSuspended => "account suspended",
-            Self::AccountNotExisted => "account not existed",
+        impl TweetFailReason {
+            pub const ALL: &'static [TweetFailReason] = &[$(Self::$variant),+];
+
+            pub fn as_str(&self) -> &'static str {
+                match self {
+                    $(Self::$variant => $text),+
+                }
+            }
         }
-        .into()
+    };
+}
+
+fail_reasons! {
+    Restricted => "restricted",
+    Deleted => "deleted",
+    AccountSuspended => "account suspended",
+    AccountNotExisted => "account not existed",
+}
+
+impl TweetFailReason {
+    pub fn from_str(s: &str) -> Option<Self> {
+        Self::ALL.iter().find(|v| v.as_str() == s).copied()
+    }
+
+    /// `'a', 'b', ...` for the CHECK constraint of `fail.type`.
+    fn sql_check_list() -> String {
+        Self::ALL
+            .iter()
+            .map(|v| format!("'{}'", v.as_str()))
+            .collect::<Vec<String>>()
+            .join(", ")
     }
 }
 
-impl Into<Error> for TweetFailReason {
-    fn into(self) -> Error {
-        match self {
-            Self::Restricted => Error::TweetRestricted,
-            Self::Deleted => Error::TweetNotExists,
-            Self::AccountSuspended => Error::TwitterAccountSuspended,
-            Self::AccountNotExisted => Error::TwitterAccountNotExisted,
+impl Display for TweetFailReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl From<TweetFailReason> for Error {
+    fn from(val: TweetFailReason) -> Self {
+        match val {
+            TweetFailReason::Restricted => Error::TweetRestricted,
+            TweetFailReason::Deleted => Error::TweetNotExists,
+            TweetFailReason::AccountSuspended => Error::TwitterAccountSuspended,
+            TweetFailReason::AccountNotExisted => Error::TwitterAccountNotExisted,
         }
     }
 }
@@ -69,17 +341,84 @@ impl TryFrom<String> for TweetFailReason {
     type Error = ();
 
     fn try_from(value: String) -> std::result::Result<Self, Self::Error> {
-        let value = value.as_str();
-        match value {
-            "restricted" => Ok(Self::Restricted),
-            "deleted" => Ok(Self::Deleted),
-            "account suspended" => Ok(Self::AccountSuspended),
-            "account not existed" => Ok(Self::AccountNotExisted),
-            _ => Err(()),
-        }
+        Self::from_str(&value).ok_or(())
     }
 }
 
+// Columns added after the initial schema: (table, column, definition).
+const ADDED_COLUMNS: &[(&str, &str, &str)] = &[
+    ("tweet", "view_count", "INTEGER"),
+    ("tweet", "conversation_id", "INTEGER"),
+    ("tweet", "in_reply_to_status_id", "INTEGER"),
+    ("tweet", "in_reply_to_screen_name", "TEXT"),
+    ("tweet", "lang", "TEXT"),
+    ("tweet", "url", "TEXT"),
+    ("media", "thumbnail_url", "TEXT"),
+    ("tweet", "index_seq", "INTEGER"),
+    ("tweet", "fetched_via", "TEXT"),
+];
+
+// Tables added after the initial schema.
+const ADDED_TABLES: &[&str] = &[r#"
+CREATE TABLE IF NOT EXISTS "user" (
+    "id"            INTEGER NOT NULL UNIQUE,
+    "screen_name"   TEXT NOT NULL,
+    "name"          TEXT NOT NULL,
+    "bio"           TEXT,
+    "followers"     INTEGER,
+    "avatar_url"    TEXT,
+    "snapshot_time" TIMESTAMP NOT NULL DEFAULT (STRFTIME('%s', 'now')),
+    PRIMARY KEY("id")
+);"#, r#"
+CREATE TABLE IF NOT EXISTS "media_checksum" (
+    "media_id"      TEXT NOT NULL,
+    "path"          TEXT NOT NULL,
+    "algo"          TEXT NOT NULL,
+    "digest"        TEXT NOT NULL,
+    "verified_at"   TIMESTAMP NOT NULL DEFAULT (STRFTIME('%s', 'now')),
+    PRIMARY KEY("media_id", "algo")
+);"#, r#"
+CREATE TABLE IF NOT EXISTS "media_fail" (
+    "path"          TEXT NOT NULL,
+    "url"           TEXT NOT NULL,
+    "media_id"      TEXT,
+    "error"         TEXT NOT NULL,
+    "fail_time"     TIMESTAMP NOT NULL DEFAULT (STRFTIME('%s', 'now')),
+    PRIMARY KEY("path")
+);"#, r#"
+CREATE TABLE IF NOT EXISTS "reply" (
+    "tweet_id"                  INTEGER NOT NULL,
+    "in_reply_to_id"            INTEGER NOT NULL,
+    "in_reply_to_screen_name"   TEXT,
+    PRIMARY KEY("tweet_id")
+);"#, r#"
+CREATE TABLE IF NOT EXISTS "edit_history" (
+    "tweet_id"          INTEGER NOT NULL,
+    "version_no"        INTEGER NOT NULL,
+    "version_tweet_id"  INTEGER NOT NULL,
+    PRIMARY KEY("tweet_id", "version_no")
+);"#];
+
+// Insert order of tweets: the insert time in microseconds (millisecond precision), bumped past
+// the latest one so tweets stored within the same millisecond still sort apart.
+const NEXT_INDEX_SEQ: &str = r#"MAX(
+    CAST(STRFTIME('%s', 'now') AS INTEGER) * 1000000
+        + CAST(SUBSTR(STRFTIME('%f', 'now'), 4) AS INTEGER) * 1000,
+    COALESCE((SELECT MAX(index_seq) FROM tweet), 0) + 1)"#;
+
+// One row per (tweet, media), the same media can be attached to several tweets.
+const MEDIA_TABLE: &str = r#"
+	"id"	TEXT NOT NULL,
+	"tweet_id"	INTEGER NOT NULL,
+	"url"	TEXT NOT NULL,
+	"width" INTEGER,
+	"height" INTEGER,
+	"no"	INTEGER,
+	"type" TEXT,
+	"thumbnail_url"	TEXT,
+	PRIMARY KEY("tweet_id", "id")
+"#;
+
 #[derive(Clone)]
 pub struct TweetDB {
     conn_pool: r2d2::Pool<SqliteConnectionManager>,
@@ -87,6 +426,10 @@ pub struct TweetDB {
 
 impl TweetDB {
     pub fn new(db_path: &Path) -> Result<Self> {
+        Self::with_tuning(db_path, DBTuning::default())
+    }
+
+    pub fn with_tuning(db_path: &Path, tuning: DBTuning) -> Result<Self> {
         if db_path.exists() {
             if !db_path.is_file() {
                 return Err(Error::CustomError {
@@ -94,17 +437,18 @@ impl TweetDB {
                 }
                 .into());
             }
-            let db = SqliteConnectionManager::file(db_path);
+            let db = tuning.manager(db_path);
             let conn_pool = r2d2::Pool::builder()
                 .connection_timeout(Duration::from_secs(2 * 60 * 60))
                 .build(db)?;
+            Self::migrate(&*conn_pool.get()?)?;
             Ok(Self { conn_pool })
         } else {
-            let db = SqliteConnectionManager::file(db_path);
+            let db = tuning.manager(db_path);
             let conn_pool = r2d2::Pool::builder()
                 .connection_timeout(Duration::from_secs(2 * 60 * 60))
                 .build(db)?;
-            conn_pool.get()?.execute_batch(
+            conn_pool.get()?.execute_batch(&format!(
                 r#"
 CREATE TABLE "tweet" (
 	"id"	INTEGER NOT NULL UNIQUE,
@@ -113,18 +457,17 @@ CREATE TABLE "tweet" (
 	"create_time"	TIMESTAMP NOT NULL,
 	"index_time"	TIMESTAMP NOT NULL DEFAULT (STRFTIME('%s', 'now')),
 	"fetch_time"	TIMESTAMP NOT NULL DEFAULT (STRFTIME('%s', 'now')),
+	"view_count"	INTEGER,
+	"conversation_id"	INTEGER,
+	"in_reply_to_status_id"	INTEGER,
+	"in_reply_to_screen_name"	TEXT,
+	"lang"	TEXT,
+	"url"	TEXT,
+	"index_seq"	INTEGER,
+	"fetched_via"	TEXT,
 	PRIMARY KEY("id")
 );
-CREATE TABLE "media" (
-	"id"	TEXT NOT NULL UNIQUE,
-	"tweet_id"	INTEGER NOT NULL,
-	"url"	TEXT NOT NULL UNIQUE,
-	"width" INTEGER,
-	"height" INTEGER,
-	"no"	INTEGER,
-	"type" TEXT,
-	PRIMARY KEY("id")
-);
+CREATE TABLE "media" ({});
 CREATE TABLE "thread" (
     "tweet_id"          INTEGER NOT NULL UNIQUE,
     "thread_master_id"  INTEGER NOT NULL,
@@ -138,33 +481,112 @@ CREATE TABLE "fail" (
     "id" INTEGER,
     "tweet_id" INTEGER NOT NULL,
     "url" TEXT NOT NULL,
-    "type" TEXT NOT NULL CHECK ("type" IN ('restricted', 'deleted', 'account suspended', 'account not existed')),
+    "type" TEXT NOT NULL CHECK ("type" IN ({})),
     PRIMARY KEY("id")
 );
                 "#,
-            )?;
+                MEDIA_TABLE,
+                TweetFailReason::sql_check_list()
+            ))?;
+            Self::migrate(&*conn_pool.get()?)?;
             Ok(Self { conn_pool })
         }
     }
 
-    pub fn is_exist(&self, id: u64) -> bool {
-        let conn = self.conn_pool.get().unwrap();
-        conn.query_row(
-            "SELECT EXISTS(SELECT 1 FROM tweet WHERE id=?1) OR EXISTS(SELECT 1 FROM fail WHERE tweet_id=?1);",
-            params![id],
-            |v| v.get(0),
-        )
-        .unwrap()
+    /// Open an existing TweetDB without ever writing to it. Fails instead of creating a missing
+    /// DB, and instead of migrating one written by an older version.
+    pub fn open_readonly(db_path: &Path, tuning: DBTuning) -> Result<Self> {
+        if !db_path.is_file() {
+            return Err(Error::CustomError {
+                msg: format!("TweetDB `{}` not exists", db_path.display()),
+            }
+            .into());
+        }
+        let db = tuning
+            .manager(db_path)
+            .with_flags(OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX);
+        let conn_pool = r2d2::Pool::builder()
+            .connection_timeout(Duration::from_secs(2 * 60 * 60))
+            .build(db)?;
+        let conn = conn_pool.get()?;
+        for (table, column, _) in ADDED_COLUMNS {
+            let existed: bool = conn.query_row(
+                "SELECT EXISTS(SELECT 1 FROM pragma_table_info(?1) WHERE name=?2);",
+                params![table, column],
+                |v| v.get(0),
+            )?;
+            if !existed {
+                return Err(Error::CustomError {
+                    msg: format!(
+                        "TweetDB `{}` is from an older version, open it once with the fetcher or manager to migrate",
+                        db_path.display()
+                    ),
+                }
+                .into());
+            }
+        }
+        drop(conn);
+        Ok(Self { conn_pool })
     }
 
-    pub fn is_restricted(&self, id: u64) -> bool {
-        let conn = self.conn_pool.get().unwrap();
-        conn.query_row(
-            "SELECT EXISTS(SELECT 1 FROM fail WHERE tweet_id=?1 AND type='restricted');",
-            params![id],
+    fn migrate(conn: &Connection) -> Result<()> {
+        let has_reply: bool = conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM sqlite_master WHERE type='table' AND name='reply');",
+            [],
             |v| v.get(0),
-        )
-            .unwrap()
+        )?;
+        for table in ADDED_TABLES {
+            conn.execute_batch(table)?;
+        }
+        // media used to be unique by url and by id alone, which dropped shared media
+        let unique_url: bool = conn.query_row(
+            r#"SELECT EXISTS(SELECT 1
+                FROM pragma_index_list('media') AS l, pragma_index_info(l.name) AS i
+                WHERE l."unique" AND i.name = 'url');"#,
+            [],
+            |v| v.get(0),
+        )?;
+        if unique_url {
+            info!("Migrating TweetDB: make media unique by (tweet_id, id)");
+            conn.execute_batch(&format!(
+                r#"BEGIN;
+                CREATE TABLE "media_new" ({});
+                INSERT INTO "media_new" (id, tweet_id, url, width, height, no, type)
+                    SELECT id, tweet_id, url, width, height, no, type FROM "media";
+                DROP TABLE "media";
+                ALTER TABLE "media_new" RENAME TO "media";
+                COMMIT;"#,
+                MEDIA_TABLE
+            ))?;
+        }
+        for (table, column, definition) in ADDED_COLUMNS {
+            let existed: bool = conn.query_row(
+                "SELECT EXISTS(SELECT 1 FROM pragma_table_info(?1) WHERE name=?2);",
+                params![table, column],
+                |v| v.get(0),
+            )?;
+            if !existed {
+                info!("Migrating TweetDB: add column {}.{}", table, column);
+                conn.execute_batch(&format!(
+                    r#"ALTER TABLE "{}" ADD COLUMN "{}" {};"#,
+                    table, column, definition
+                ))?;
+            }
+        }
+        // tweets stored before index_seq keep the order index_time gives them
+        conn.execute_batch(
+            r#"CREATE INDEX IF NOT EXISTS "tweet_index_seq" ON "tweet" ("index_seq");
+            UPDATE tweet SET index_seq = index_time * 1000000 WHERE index_seq IS NULL;"#,
+        )?;
+        if !has_reply {
+            // reply edges of tweets stored before the table existed
+            conn.execute_batch(
+                r#"INSERT OR IGNORE INTO reply (tweet_id, in_reply_to_id, in_reply_to_screen_name)
+                    SELECT id, in_reply_to_status_id, in_reply_to_screen_name FROM tweet
+                    WHERE in_reply_to_status_id IS NOT NULL;"#,
+            )?;
+        }
+        Ok(())
     }
 
     fn do_rusqlite_error<S: AsRef<str>>(
@@ -177,25 +599,297 @@ CREATE TABLE "fail" (
                 if c == allow {
                     // allow
                 } else {
-                    error!("{}: {}", err_title.as_ref(), err.to_string());
+                    error!("{}: {}", err_title.as_ref(), err);
                 }
             } else {
-                error!("{}: {}", err_title.as_ref(), err.to_string());
+                error!("{}: {}", err_title.as_ref(), err);
                 panic!();
             }
         } else {
-            error!("{}: {}", err_title.as_ref(), err.to_string());
+            error!("{}: {}", err_title.as_ref(), err);
             panic!();
         }
     }
 
-    pub fn insert_tweet(&self, tweet: &Tweet) {
-        let conn = self.conn_pool.get().unwrap();
+    fn table_columns(conn: &Connection, schema: &str, table: &str) -> Result<Vec<String>> {
+        let mut stmt = conn.prepare("SELECT name FROM pragma_table_info(?1, ?2);")?;
+        let columns = stmt
+            .query_map(params![table, schema], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<String>>>()?;
+        Ok(columns)
+    }
+
+    /// Columns of `table` in the main db that the attached `other` db has too, quoted. Empty
+    /// when `other` has no such table.
+    fn shared_columns(conn: &Connection, table: &str) -> Result<Vec<String>> {
+        let other = Self::table_columns(conn, "other", table)?;
+        Ok(Self::table_columns(conn, "main", table)?
+            .into_iter()
+            .filter(|v| other.contains(v))
+            .map(|v| format!(r#""{}""#, v))
+            .collect())
+    }
+
+    /// Copy everything from another TweetDB, keeping the newer `fetch_time` on conflicts. The
+    /// other db is only read, columns and tables it predates are left to their defaults.
+    pub fn merge_from(&self, other: &Path) -> Result<()> {
+        if !other.is_file() {
+            return Err(Error::CustomError {
+                msg: format!("TweetDB `{}` not exists", other.display()),
+            }
+            .into());
+        }
+        let mut conn = self.conn_pool.get()?;
+        // `?` and `#` would end the path of the uri
+        let uri = format!(
+            "file:{}?mode=ro",
+            other
+                .to_string_lossy()
+                .replace('%', "%25")
+                .replace('?', "%3f")
+                .replace('#', "%23")
+        );
+        conn.execute("ATTACH DATABASE ?1 AS other;", params![uri])?;
+        let result = (|| -> Result<()> {
+            let tx = conn.transaction()?;
+
+            // merged and updated tweets are new to this db, `export --append` must see them
+            let columns = Self::shared_columns(&tx, "tweet")?
+                .into_iter()
+                .filter(|v| v.as_str() != r#""index_seq""#)
+                .collect::<Vec<String>>();
+            let updates = columns
+                .iter()
+                .filter(|v| v.as_str() != r#""id""#)
+                .map(|v| format!("{}=excluded.{}", v, v))
+                .chain([r#""index_seq"=excluded."index_seq""#.to_string()])
+                .collect::<Vec<String>>();
+            let count = tx.execute(
+                &format!(
+                    r#"INSERT INTO main.tweet ({0}, "index_seq")
+                        SELECT {0}, {2} + ROW_NUMBER() OVER (ORDER BY id) - 1
+                        FROM other.tweet WHERE true
+                        ON CONFLICT(id) DO UPDATE SET {1}
+                        WHERE excluded.fetch_time > tweet.fetch_time;"#,
+                    columns.join(", "),
+                    updates.join(", "),
+                    NEXT_INDEX_SEQ
+                ),
+                [],
+            )?;
+            info!("Merged {} tweets.", count);
+
+            for table in [
+                "media",
+                "thread",
+                "reply",
+                "edit_history",
+                "user",
+                "media_checksum",
+            ] {
+                let columns = Self::shared_columns(&tx, table)?;
+                if columns.is_empty() {
+                    info!("No {} table to merge.", table);
+                    continue;
+                }
+                // UNIQUE violations mean we already have it
+                let count = tx.execute(
+                    &format!(
+                        r#"INSERT OR IGNORE INTO main."{0}" ({1}) SELECT {1} FROM other."{0}";"#,
+                        table,
+                        columns.join(", ")
+                    ),
+                    [],
+                )?;
+                info!("Merged {} {} rows.", count, table);
+            }
+
+            let count = tx.execute(
+                r#"INSERT INTO main.fail (tweet_id, url, type)
+                    SELECT tweet_id, url, type FROM other.fail
+                    WHERE tweet_id NOT IN (SELECT tweet_id FROM main.fail)
+                    AND tweet_id NOT IN (SELECT id FROM main.tweet);"#,
+                [],
+            )?;
+            info!("Merged {} fails.", count);
+
+            tx.commit()?;
+            Ok(())
+        })();
+        conn.execute("DETACH DATABASE other;", [])?;
+        result
+    }
+
+    /// Delete rows left behind by tweets that are no longer stored, returning the count removed
+    /// from each table. `fail` is left alone, its tweets are never stored by design.
+    pub fn prune_orphans(&self) -> Result<Vec<(&'static str, usize)>> {
+        const ORPHANS: &[(&str, &str)] = &[
+            ("media", "tweet_id NOT IN (SELECT id FROM tweet)"),
+            (
+                "thread",
+                "tweet_id NOT IN (SELECT id FROM tweet) OR thread_master_id NOT IN (SELECT id FROM tweet)",
+            ),
+            ("reply", "tweet_id NOT IN (SELECT id FROM tweet)"),
+            ("edit_history", "tweet_id NOT IN (SELECT id FROM tweet)"),
+            // after media, they hang off it
+            ("media_checksum", "media_id NOT IN (SELECT id FROM media)"),
+            (
+                "media_fail",
+                "media_id IS NOT NULL AND media_id NOT IN (SELECT id FROM media)",
+            ),
+        ];
+        let mut conn = self.conn_pool.get()?;
+        let tx = conn.transaction()?;
+        let mut removed = vec![];
+        for (table, condition) in ORPHANS {
+            let count = tx.execute(
+                &format!(r#"DELETE FROM "{}" WHERE {};"#, table, condition),
+                [],
+            )?;
+            removed.push((*table, count));
+        }
+        tx.commit()?;
+        Ok(removed)
+    }
+
+    /// Record the digest of a freshly downloaded media file.
+    pub fn set_media_checksum(
+        &self,
+        media_id: &str,
+        path: &str,
+        algo: &str,
+        digest: &str,
+    ) -> Result<()> {
+        self.conn_pool.get()?.execute(
+            r#"INSERT INTO media_checksum (media_id, path, algo, digest) VALUES (?1, ?2, ?3, ?4)
+                ON CONFLICT(media_id, algo) DO UPDATE SET
+                path=excluded.path, digest=excluded.digest, verified_at=excluded.verified_at;"#,
+            params![media_id, path, algo, digest],
+        )?;
+        Ok(())
+    }
+
+    pub fn get_media_checksums(&self) -> Result<Vec<MediaChecksum>> {
+        let conn = self.conn_pool.get()?;
+        let mut stmt =
+            conn.prepare("SELECT media_id, path, algo, digest, verified_at FROM media_checksum;")?;
+        let result = stmt
+            .query_map([], |row| {
+                Ok(MediaChecksum {
+                    media_id: row.get(0)?,
+                    path: row.get(1)?,
+                    algo: row.get(2)?,
+                    digest: row.get(3)?,
+                    verified_at: row.get(4)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<MediaChecksum>>>()?;
+        Ok(result)
+    }
+
+    pub fn set_media_fail(&self, fail: &MediaFail) -> Result<()> {
+        self.conn_pool.get()?.execute(
+            r#"INSERT INTO media_fail (path, url, media_id, error) VALUES (?1, ?2, ?3, ?4)
+                ON CONFLICT(path) DO UPDATE SET
+                url=excluded.url, media_id=excluded.media_id, error=excluded.error,
+                fail_time=STRFTIME('%s', 'now');"#,
+            params![fail.path, fail.url, fail.media_id, fail.error],
+        )?;
+        Ok(())
+    }
+
+    pub fn get_media_fails(&self) -> Result<Vec<MediaFail>> {
+        let conn = self.conn_pool.get()?;
+        let mut stmt = conn.prepare("SELECT path, url, media_id, error FROM media_fail;")?;
+        let result = stmt
+            .query_map([], |row| {
+                Ok(MediaFail {
+                    path: row.get(0)?,
+                    url: row.get(1)?,
+                    media_id: row.get(2)?,
+                    error: row.get(3)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<MediaFail>>>()?;
+        Ok(result)
+    }
+
+    /// Ids of every version of `tweet_id`, oldest first. Empty when it was never edited.
+    pub fn get_edit_history(&self, tweet_id: u64) -> Result<Vec<u64>> {
+        let conn = self.conn_pool.get()?;
+        let mut stmt = conn.prepare(
+            "SELECT version_tweet_id FROM edit_history WHERE tweet_id = ?1 ORDER BY version_no;",
+        )?;
+        let result = stmt
+            .query_map(params![tweet_id], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<u64>>>()?;
+        Ok(result)
+    }
+
+    pub fn remove_media_fail(&self, path: &str) -> Result<()> {
+        self.conn_pool
+            .get()?
+            .execute("DELETE FROM media_fail WHERE path = ?1;", params![path])?;
+        Ok(())
+    }
+
+    /// Recorded failures with one of `reasons`, or all of them if `reasons` is empty.
+    pub fn list_fails(&self, reasons: &[TweetFailReason]) -> Result<Vec<TweetFail>> {
+        let conn = self.conn_pool.get()?;
+        let mut stmt = conn.prepare("SELECT tweet_id, url, type FROM fail ORDER BY type, id;")?;
+        let result = stmt
+            .query_map([], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get::<_, String>(2)?))
+            })?
+            .collect::<rusqlite::Result<Vec<(u64, String, String)>>>()?
+            .into_iter()
+            .filter_map(|(tweet_id, url, reason)| {
+                // the CHECK constraint keeps unknown reasons out
+                let reason = TweetFailReason::from_str(&reason)?;
+                Some(TweetFail {
+                    tweet_id,
+                    url,
+                    reason,
+                })
+            })
+            .filter(|v| reasons.is_empty() || reasons.contains(&v.reason))
+            .collect();
+        Ok(result)
+    }
+
+    /// Mark a checksum as just verified against the file on disk.
+    pub fn touch_media_checksum(&self, media_id: &str, algo: &str) -> Result<()> {
+        self.conn_pool.get()?.execute(
+            r#"UPDATE media_checksum SET verified_at = STRFTIME('%s', 'now')
+                WHERE media_id = ?1 AND algo = ?2;"#,
+            params![media_id, algo],
+        )?;
+        Ok(())
+    }
+
+    fn write_tweet(conn: &Connection, tweet: &Tweet) {
         if let Err(e) = conn.execute(
-            r#"INSERT INTO tweet 
-                    (id, author, content, create_time) 
-                    VALUES (?1, ?2, ?3, ?4);"#,
-            params![tweet.id, tweet.author, tweet.content, tweet.create_time],
+            &format!(
+                r#"INSERT INTO tweet 
+                    (id, author, content, create_time, view_count, conversation_id,
+                    in_reply_to_status_id, in_reply_to_screen_name, lang, url, fetched_via,
+                    index_seq) 
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, {});"#,
+                NEXT_INDEX_SEQ
+            ),
+            params![
+                tweet.id,
+                tweet.author,
+                tweet.content,
+                tweet.create_time,
+                tweet.view_count,
+                tweet.conversation_id,
+                tweet.in_reply_to_status_id,
+                tweet.in_reply_to_screen_name,
+                tweet.lang,
+                tweet.url,
+                tweet.fetched_via
+            ],
         ) {
             Self::do_rusqlite_error(
                 format!("Error when inserting tweet {}/{}", tweet.author, tweet.id),
@@ -205,10 +899,199 @@ CREATE TABLE "fail" (
         }
     }
 
-    pub fn get_tweet(&self, id: u64) -> Result<Tweet> {
+    fn write_user(conn: &Connection, user: &User) {
+        if let Err(e) = conn.execute(
+            r#"INSERT INTO user
+                    (id, screen_name, name, bio, followers, avatar_url)
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+                    ON CONFLICT(id) DO UPDATE SET
+                    screen_name=excluded.screen_name, name=excluded.name, bio=excluded.bio,
+                    followers=excluded.followers, avatar_url=excluded.avatar_url,
+                    snapshot_time=excluded.snapshot_time;"#,
+            params![
+                user.id,
+                user.screen_name,
+                user.name,
+                user.bio,
+                user.followers,
+                user.avatar_url
+            ],
+        ) {
+            Self::do_rusqlite_error(
+                format!("Error when inserting user {}/{}", user.screen_name, user.id),
+                e,
+                None,
+            );
+        }
+    }
+
+    fn write_media(conn: &Connection, media: &Media) {
+        if let Err(e) = conn.execute(
+            r#"INSERT INTO media 
+                    (id, tweet_id, url, width, height, no, type, thumbnail_url) 
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8);"#,
+            params![
+                media.id,
+                media.tweet_id,
+                media.url,
+                media.width,
+                media.height,
+                media.no,
+                media._type,
+                media.thumbnail_url
+            ],
+        ) {
+            Self::do_rusqlite_error(
+                format!("Error when inserting media {}/{}", media.tweet_id, media.id),
+                e,
+                Some(rusqlite::ErrorCode::ConstraintViolation),
+            );
+        }
+    }
+
+    fn write_thread(conn: &Connection, thread_info: &ThreadInfo) {
+        // A thread whose root or parent was never fetched can't be linked, skip the edge
+        // instead of inventing a placeholder tweet. Checked here rather than left to the
+        // foreign keys, which SQLite doesn't enforce unless asked per connection.
+        match conn.execute(
+            r#"INSERT INTO thread 
+                    (tweet_id, thread_master_id, in_reply_to) 
+                    SELECT ?1, ?2, ?3
+                    WHERE EXISTS (SELECT 1 FROM tweet WHERE id = ?1)
+                        AND EXISTS (SELECT 1 FROM tweet WHERE id = ?2)
+                        AND EXISTS (SELECT 1 FROM tweet WHERE id = ?3);"#,
+            params![
+                thread_info.tweet_id,
+                thread_info.thread_id,
+                thread_info.reply_to
+            ],
+        ) {
+            Ok(0) => warn!(
+                "Thread {} of {} references a tweet not in the archive, skipped.",
+                thread_info.thread_id, thread_info.tweet_id
+            ),
+            Ok(_) => {}
+            Err(e) => Self::do_rusqlite_error(
+                format!("Error when inserting thread {}", thread_info.tweet_id),
+                e,
+                Some(rusqlite::ErrorCode::ConstraintViolation),
+            ),
+        }
+    }
+
+    fn write_reply(conn: &Connection, reply: &ReplyInfo) {
+        if let Err(e) = conn.execute(
+            r#"INSERT OR REPLACE INTO reply
+                    (tweet_id, in_reply_to_id, in_reply_to_screen_name)
+                    VALUES (?1, ?2, ?3);"#,
+            params![
+                reply.tweet_id,
+                reply.in_reply_to_id,
+                reply.in_reply_to_screen_name
+            ],
+        ) {
+            Self::do_rusqlite_error(
+                format!("Error when inserting reply {}", reply.tweet_id),
+                e,
+                None,
+            );
+        }
+    }
+
+    fn write_edit_version(conn: &Connection, version: &EditVersion) {
+        if let Err(e) = conn.execute(
+            r#"INSERT OR REPLACE INTO edit_history
+                    (tweet_id, version_no, version_tweet_id)
+                    VALUES (?1, ?2, ?3);"#,
+            params![
+                version.tweet_id,
+                version.version_no,
+                version.version_tweet_id
+            ],
+        ) {
+            Self::do_rusqlite_error(
+                format!("Error when inserting edit history of {}", version.tweet_id),
+                e,
+                None,
+            );
+        }
+    }
+
+    pub fn get_db_conn(&self) -> PooledConnection<SqliteConnectionManager> {
+        self.conn_pool.get().unwrap()
+    }
+}
+
+/// Storage operations the fetcher and summarizer rely on, so an archive can live
+/// somewhere other than a local SQLite file.
+pub trait TweetStore: Send + Sync {
+    /// Whether `id` is stored, either as a tweet or as a recorded failure.
+    fn is_exist(&self, id: u64) -> bool;
+    fn is_restricted(&self, id: u64) -> bool;
+    /// Newest stored tweet id of every author, keyed by the lowercased screen name.
+    fn latest_tweet_ids(&self) -> Result<HashMap<String, u64>>;
+    fn insert_tweet(&self, tweet: &Tweet);
+    fn get_tweet(&self, id: u64) -> Result<Tweet>;
+    fn insert_user(&self, user: &User);
+    fn insert_media(&self, media: &Media);
+    /// Whether the media `id` is already stored for `tweet_id`, to skip inserting it again.
+    /// The same media may belong to other tweets too.
+    fn contains_media(&self, tweet_id: u64, id: &str) -> bool;
+    fn get_medias(&self, tweet_id: u64) -> Result<Vec<Media>>;
+    /// Every stored tweet of the thread `id` belongs to, oldest first. Empty when not in a thread.
+    fn get_thread_tweets(&self, id: u64) -> Result<Vec<Tweet>>;
+    /// Skips the edge when it references a tweet that is not stored.
+    fn insert_thread(&self, thread_info: &ThreadInfo);
+    /// The replied tweet doesn't have to be stored.
+    fn insert_reply(&self, reply: &ReplyInfo);
+    fn insert_edit_version(&self, version: &EditVersion);
+    fn insert_fail(&self, url: &str, reason: TweetFailReason);
+    /// Insert everything in `batch` like the single inserts do, in one transaction.
+    fn insert_batch(&self, batch: &TweetBatch) -> Result<()>;
+}
+
+impl TweetStore for TweetDB {
+    fn is_exist(&self, id: u64) -> bool {
+        let conn = self.conn_pool.get().unwrap();
+        conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM tweet WHERE id=?1) OR EXISTS(SELECT 1 FROM fail WHERE tweet_id=?1);",
+            params![id],
+            |v| v.get(0),
+        )
+        .unwrap()
+    }
+
+    fn is_restricted(&self, id: u64) -> bool {
+        let conn = self.conn_pool.get().unwrap();
+        conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM fail WHERE tweet_id=?1 AND type='restricted');",
+            params![id],
+            |v| v.get(0),
+        )
+            .unwrap()
+    }
+
+    fn latest_tweet_ids(&self) -> Result<HashMap<String, u64>> {
+        let conn = self.conn_pool.get()?;
+        let mut stmt =
+            conn.prepare("SELECT LOWER(author), MAX(id) FROM tweet GROUP BY LOWER(author);")?;
+        let result = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<HashMap<String, u64>>>()?;
+        Ok(result)
+    }
+
+    fn insert_tweet(&self, tweet: &Tweet) {
+        Self::write_tweet(&self.conn_pool.get().unwrap(), tweet)
+    }
+
+    fn get_tweet(&self, id: u64) -> Result<Tweet> {
         let conn = self.conn_pool.get().unwrap();
         let t = conn.query_row(
-            "SELECT author, content, create_time FROM tweet WHERE id = ?",
+            r#"SELECT author, content, create_time, view_count, index_time, fetch_time,
+                conversation_id, in_reply_to_status_id, in_reply_to_screen_name, lang, url,
+                fetched_via
+                FROM tweet WHERE id = ?"#,
             params![id],
             |row| {
                 Ok(Tweet {
@@ -216,6 +1099,15 @@ CREATE TABLE "fail" (
                     author: row.get(0)?,
                     content: row.get(1)?,
                     create_time: row.get(2)?,
+                    view_count: row.get(3)?,
+                    index_time: row.get(4)?,
+                    fetch_time: row.get(5)?,
+                    conversation_id: row.get(6)?,
+                    in_reply_to_status_id: row.get(7)?,
+                    in_reply_to_screen_name: row.get(8)?,
+                    lang: row.get(9)?,
+                    url: row.get(10)?,
+                    fetched_via: row.get(11)?,
                 })
             },
         );
@@ -223,7 +1115,7 @@ CREATE TABLE "fail" (
             let err: String = if let Ok(err) = conn.query_row(
                 "SELECT type FROM fail WHERE tweet_id = ?",
                 params![id],
-                |row| Ok(row.get(0)?),
+                |row| row.get(0),
             ) {
                 err
             } else {
@@ -243,34 +1135,30 @@ CREATE TABLE "fail" (
         }
     }
 
-    pub fn insert_media(&self, media: &Media) {
+    /// Upsert the profile snapshot of a user.
+    fn insert_user(&self, user: &User) {
+        Self::write_user(&self.conn_pool.get().unwrap(), user)
+    }
+
+    fn insert_media(&self, media: &Media) {
+        Self::write_media(&self.conn_pool.get().unwrap(), media)
+    }
+
+    fn contains_media(&self, tweet_id: u64, id: &str) -> bool {
         let conn = self.conn_pool.get().unwrap();
-        if let Err(e) = conn.execute(
-            r#"INSERT INTO media 
-                    (id, tweet_id, url, width, height, no, type) 
-                    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7);"#,
-            params![
-                media.id,
-                media.tweet_id,
-                media.url,
-                media.width,
-                media.height,
-                media.no,
-                media._type
-            ],
-        ) {
-            Self::do_rusqlite_error(
-                format!("Error when inserting media {}/{}", media.tweet_id, media.id),
-                e,
-                Some(rusqlite::ErrorCode::ConstraintViolation),
-            );
-        }
+        conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM media WHERE tweet_id=?1 AND id=?2);",
+            params![tweet_id, id],
+            |v| v.get(0),
+        )
+        .unwrap()
     }
 
-    pub fn get_medias(&self, tweet_id: u64) -> Result<Vec<Media>> {
+    fn get_medias(&self, tweet_id: u64) -> Result<Vec<Media>> {
         let conn = self.conn_pool.get().unwrap();
-        let mut stmt =
-            conn.prepare("SELECT id, url, width, height, no, type FROM media WHERE tweet_id=?;")?;
+        let mut stmt = conn.prepare(
+            "SELECT id, url, width, height, no, type, thumbnail_url FROM media WHERE tweet_id=?;",
+        )?;
         let result = stmt
             .query_map(params![tweet_id], |row| {
                 Ok(Media {
@@ -281,6 +1169,7 @@ CREATE TABLE "fail" (
                     height: row.get(3)?,
                     no: row.get(4)?,
                     _type: row.get(5)?,
+                    thumbnail_url: row.get(6)?,
                 })
             })?
             .map(|v| v.unwrap())
@@ -289,27 +1178,41 @@ CREATE TABLE "fail" (
         Ok(result)
     }
 
-    pub fn insert_thread(&self, thread_info: &ThreadInfo) {
-        let conn = self.conn_pool.get().unwrap();
-        if let Err(e) = conn.execute(
-            r#"INSERT INTO thread 
-                    (tweet_id, thread_master_id, in_reply_to) 
-                    VALUES (?1, ?2, ?3);"#,
-            params![
-                thread_info.tweet_id,
-                thread_info.thread_id,
-                thread_info.reply_to
-            ],
-        ) {
-            Self::do_rusqlite_error(
-                format!("Error when inserting thread {}", thread_info.tweet_id),
-                e,
-                Some(rusqlite::ErrorCode::ConstraintViolation),
-            );
-        }
+    fn get_thread_tweets(&self, id: u64) -> Result<Vec<Tweet>> {
+        let conn = self.conn_pool.get()?;
+        let mut stmt = conn.prepare(
+            r#"WITH master AS (
+                    SELECT thread_master_id AS id FROM thread WHERE tweet_id = ?1
+                    UNION SELECT thread_master_id FROM thread WHERE thread_master_id = ?1)
+                SELECT id FROM master
+                UNION SELECT tweet_id FROM thread WHERE thread_master_id IN (SELECT id FROM master)
+                ORDER BY 1;"#,
+        )?;
+        let ids = stmt
+            .query_map(params![id], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<u64>>>()?;
+        drop(stmt);
+        drop(conn);
+        // the thread root may have failed while its replies were stored
+        Ok(ids
+            .into_iter()
+            .filter_map(|v| self.get_tweet(v).ok())
+            .collect())
+    }
+
+    fn insert_thread(&self, thread_info: &ThreadInfo) {
+        Self::write_thread(&self.conn_pool.get().unwrap(), thread_info)
+    }
+
+    fn insert_reply(&self, reply: &ReplyInfo) {
+        Self::write_reply(&self.conn_pool.get().unwrap(), reply)
+    }
+
+    fn insert_edit_version(&self, version: &EditVersion) {
+        Self::write_edit_version(&self.conn_pool.get().unwrap(), version)
     }
 
-    pub fn insert_fail(&self, url: &str, reason: TweetFailReason) {
+    fn insert_fail(&self, url: &str, reason: TweetFailReason) {
         let id = extract_twitter_url(url).ok_or(Error::NotATweet).unwrap().1;
         let conn = self.conn_pool.get().unwrap();
         if let Err(e) = conn.execute(
@@ -318,11 +1221,33 @@ CREATE TABLE "fail" (
                     VALUES (?1, ?2, ?3);"#,
             params![id, url, reason.to_string()],
         ) {
-            error!("Error when inserting fail {}: {}", url, e.to_string());
+            error!("Error when inserting fail {}: {}", url, e);
         }
     }
 
-    pub fn get_db_conn(&self) -> PooledConnection<SqliteConnectionManager> {
-        self.conn_pool.get().unwrap()
+    fn insert_batch(&self, batch: &TweetBatch) -> Result<()> {
+        let mut conn = self.conn_pool.get()?;
+        let tx = conn.transaction()?;
+        // tweets first, threads reference them
+        for tweet in &batch.tweets {
+            Self::write_tweet(&tx, tweet);
+        }
+        for user in &batch.users {
+            Self::write_user(&tx, user);
+        }
+        for media in &batch.medias {
+            Self::write_media(&tx, media);
+        }
+        for thread in &batch.threads {
+            Self::write_thread(&tx, thread);
+        }
+        for reply in &batch.replies {
+            Self::write_reply(&tx, reply);
+        }
+        for version in &batch.edits {
+            Self::write_edit_version(&tx, version);
+        }
+        tx.commit()?;
+        Ok(())
     }
 }
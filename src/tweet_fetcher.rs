@@ -1,13 +1,20 @@
+use std::collections::{HashMap, VecDeque};
+use std::ffi::OsStr;
 use std::io::Write;
 use std::path::Path;
-use std::sync::mpsc;
+use std::sync::{mpsc, Arc, Mutex, Weak};
 use std::thread::sleep;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use anyhow::Result;
-use headless_chrome::{Browser, LaunchOptions};
+use base64::Engine;
+use headless_chrome::{Browser, LaunchOptions, Tab};
+use indicatif::ProgressBar;
+use headless_chrome::browser::tab::EventListener;
+use headless_chrome::protocol::cdp::types::Event;
 use headless_chrome::protocol::cdp::Fetch::{RequestPattern, RequestStage};
 use headless_chrome::protocol::cdp::Network::ResourceType;
+use headless_chrome::protocol::cdp::{Network, Runtime};
 use lazy_static::lazy_static;
 use log::{debug, error, info, trace, warn};
 use r2d2::PooledConnection;
@@ -15,19 +22,79 @@ use r2d2_sqlite::SqliteConnectionManager;
 use regex::Regex;
 use rusqlite::params;
 
+use crate::tweet_db::DBTuning;
 use crate::utils::extract_twitter_url;
 
 use super::twitter_def;
 use super::utils::Error;
 
+/// Chrome normally hands out its first tab within a few seconds of launching.
+const INITIAL_TAB_TIMEOUT: Duration = Duration::from_secs(30);
+
 pub struct TweetFetcher {
     browser_instance: Browser,
+    headless: bool,
+    login_step_timeout: Duration,
+    login_home_timeout: Duration,
+    expand_threads: bool,
+    body_fetch_retries: u32,
+    body_fetch_delay: Duration,
+    debug_browser: bool,
+    timings: Mutex<FetchTimings>,
+}
+
+/// Wall time of the tweet fetches, to tune the rate limit config against.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FetchTimings {
+    pub count: u32,
+    pub total: Duration,
+    pub min: Duration,
+    pub max: Duration,
+}
+
+impl FetchTimings {
+    fn record(&mut self, elapsed: Duration) {
+        self.min = if self.count == 0 {
+            elapsed
+        } else {
+            self.min.min(elapsed)
+        };
+        self.max = self.max.max(elapsed);
+        self.total += elapsed;
+        self.count += 1;
+    }
+
+    pub fn avg(&self) -> Option<Duration> {
+        (self.count > 0).then(|| self.total / self.count)
+    }
+}
+
+impl std::fmt::Display for FetchTimings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.avg() {
+            Some(avg) => write!(
+                f,
+                "{} fetches, min {} ms, avg {} ms, max {} ms",
+                self.count,
+                self.min.as_millis(),
+                avg.as_millis(),
+                self.max.as_millis()
+            ),
+            None => write!(f, "no fetches"),
+        }
+    }
 }
 
 impl TweetFetcher {
-    pub fn new<P: AsRef<Path>>(user_data_dir: P, headless: bool) -> Result<Self> {
+    /// `extra_args` are appended to chrome's command line, like `--no-sandbox` in containers.
+    pub fn new<P: AsRef<Path>>(
+        user_data_dir: P,
+        headless: bool,
+        extra_args: &[String],
+    ) -> Result<Self> {
         let browser = Browser::new(LaunchOptions {
             headless,
+            args: extra_args.iter().map(OsStr::new).collect(),
             idle_browser_timeout: Duration::from_secs(24 * 60 * 60),
             user_data_dir: Some(user_data_dir.as_ref().to_path_buf()),
             // port: Some(23333),
@@ -38,9 +105,66 @@ impl TweetFetcher {
         sleep(Duration::from_secs(1));
         Ok(Self {
             browser_instance: browser,
+            headless,
+            login_step_timeout: Duration::from_secs(10),
+            login_home_timeout: Duration::from_secs(5 * 60),
+            expand_threads: false,
+            body_fetch_retries: 20,
+            body_fetch_delay: Duration::from_millis(500),
+            debug_browser: false,
+            timings: Mutex::new(FetchTimings::default()),
         })
     }
 
+    /// The first tab of the browser. Gives up with `BrowserUnavailable` when no tab shows up in
+    /// time, which happens when chrome crashed right after launching. Chrome is killed once the
+    /// fetcher is dropped.
+    fn initial_tab(&self) -> Result<Arc<Tab>> {
+        // wait_for_initial_tab() falls back to asking chrome for a new tab, which waits for an
+        // answer as long as idle_browser_timeout, just wait for the tab chrome starts with
+        let started = Instant::now();
+        loop {
+            if let Some(tab) = self.browser_instance.get_tabs().lock().unwrap().first() {
+                return Ok(tab.clone());
+            }
+            if started.elapsed() > INITIAL_TAB_TIMEOUT {
+                error!(
+                    "Browser gave no tab in {} secs.",
+                    INITIAL_TAB_TIMEOUT.as_secs()
+                );
+                return Err(Error::BrowserUnavailable.into());
+            }
+            sleep(Duration::from_millis(100));
+        }
+    }
+
+    /// Scroll down and merge the following TweetDetail pages so long threads are complete.
+    pub fn set_expand_threads(&mut self, expand_threads: bool) {
+        self.expand_threads = expand_threads;
+    }
+
+    /// Timings of every `get_tweet` so far, failed ones included.
+    pub fn timings(&self) -> FetchTimings {
+        *self.timings.lock().unwrap()
+    }
+
+    /// Log the console errors and failed requests of the page when a fetch fails.
+    pub fn set_debug_browser(&mut self, debug_browser: bool) {
+        self.debug_browser = debug_browser;
+    }
+
+    /// How often and how long apart to retry reading a matched response body that isn't ready.
+    pub fn set_body_fetch_retry(&mut self, retries: u32, delay: Duration) {
+        self.body_fetch_retries = retries;
+        self.body_fetch_delay = delay;
+    }
+
+    /// `step` bounds waiting for each login form element, `home` bounds the final redirect.
+    pub fn set_login_timeout(&mut self, step: Duration, home: Duration) {
+        self.login_step_timeout = step;
+        self.login_home_timeout = home;
+    }
+
     pub fn get_username(&self) -> Result<Option<String>> {
         const ANALYTICS_URL: &str = "https://analytics.twitter.com/";
         const ANALYTICS_NONE_URL: &str = "https://analytics.twitter.com/about";
@@ -66,11 +190,32 @@ impl TweetFetcher {
                     return Ok(Some(username.to_string()));
                 }
             }
-            return Err(Error::CustomError {
+            Err(Error::CustomError {
                 msg: "Regex to capture username failed.".to_string(),
             }
-            .into());
+            .into())
+        }
+    }
+
+    /// Visit the home page so twitter hands out the `gt` guest token cookie, which lets
+    /// logged-out sessions see most public tweets. Returns the token if one was set.
+    pub fn acquire_guest_token(&self) -> Result<Option<String>> {
+        let tab = self.initial_tab()?;
+        tab.navigate_to(twitter_def::HOME_URL)?;
+        tab.wait_until_navigated()?;
+        // the cookie is set by a script after the page loaded
+        let start = Instant::now();
+        while start.elapsed() < self.login_step_timeout {
+            if let Some(cookie) = tab
+                .get_cookies()?
+                .into_iter()
+                .find(|v| v.name == twitter_def::GUEST_TOKEN_COOKIE)
+            {
+                return Ok(Some(cookie.value));
+            }
+            sleep(Duration::from_millis(500));
         }
+        Ok(None)
     }
 
     pub fn login<S: AsRef<str>>(
@@ -80,7 +225,7 @@ impl TweetFetcher {
         // verification_username: Option<&str>,
         login_cred: Option<(S, S, Option<S>)>,
     ) -> Result<()> {
-        let tab = self.browser_instance.wait_for_initial_tab()?;
+        let tab = self.initial_tab()?;
         tab.navigate_to(twitter_def::LOGIN_URL)?;
         if let Some((username, password, verification_username)) = login_cred {
             let username = username.as_ref();
@@ -90,7 +235,7 @@ impl TweetFetcher {
             // Username Input
             let input = tab.wait_for_element_with_custom_timeout(
                 twitter_def::LOGIN_USERNAME_SELECTOR,
-                Duration::from_secs(10),
+                self.login_step_timeout,
             )?;
             input.type_into(username)?;
             let btn = tab.wait_for_element(twitter_def::LOGIN_BUTTON_SELECTOR_NEXT)?;
@@ -103,7 +248,7 @@ impl TweetFetcher {
                         + twitter_def::LOGIN_PASSWORD_SELECTOR
                 }
                 .as_str(),
-                Duration::from_secs(10),
+                self.login_step_timeout,
             )?;
             if let Some(attr) = input.get_attributes()? {
                 let attr = attr
@@ -141,7 +286,7 @@ impl TweetFetcher {
                     btn.click()?;
                     let input = tab.wait_for_element_with_custom_timeout(
                         twitter_def::LOGIN_PASSWORD_SELECTOR,
-                        Duration::from_secs(10),
+                        self.login_step_timeout,
                     )?;
                     debug!("Login input password.");
                     input.type_into(password)?;
@@ -155,7 +300,36 @@ impl TweetFetcher {
 
             let btn = tab.find_element(twitter_def::LOGIN_BUTTON_SELECTOR_LOGIN)?;
             btn.click()?;
+            let mut start = Instant::now();
+            let mut challenge_prompted = false;
             while !tab.get_url().contains("home") {
+                if !challenge_prompted
+                    && tab
+                        .find_element(twitter_def::LOGIN_CHALLENGE_SELECTOR)
+                        .is_ok()
+                {
+                    if self.headless {
+                        error!("Login challenge (captcha) encountered in headless mode.");
+                        return Err(Error::LoginChallenge.into());
+                    }
+                    info!("Login challenge (captcha) encountered.");
+                    info!("Please solve it in the browser window and type enter to continue.");
+                    let mut line = String::new();
+                    std::io::stdin().read_line(&mut line).unwrap();
+                    challenge_prompted = true;
+                    start = Instant::now();
+                    continue;
+                }
+                if start.elapsed() > self.login_home_timeout {
+                    error!("Login not redirected to home, stuck at {}", tab.get_url());
+                    return Err(Error::LoginFailed {
+                        msg: format!(
+                            "Not redirected to home within {} secs",
+                            self.login_home_timeout.as_secs()
+                        ),
+                    }
+                    .into());
+                }
                 sleep(Duration::from_millis(500));
             }
         } else {
@@ -176,8 +350,9 @@ impl TweetFetcher {
 
     fn __get_tweet(&self, url: &str) -> Result<String> {
         // Running in single process, only requiring one tab
-        let tab = self.browser_instance.wait_for_initial_tab()?;
-        let (tx, rx) = mpsc::sync_channel::<String>(1);
+        let tab = self.initial_tab()?;
+        // (http status, body)
+        let (tx, rx) = mpsc::sync_channel::<(u32, String)>(1);
 
         const PATTERN_TWITTER_DETAILS: &str = "https://twitter.com/i/api/graphql/*";
         const PATTERN_TWITTER_DETAILS2: &str = "https://api.twitter.com/graphql/*";
@@ -196,6 +371,8 @@ impl TweetFetcher {
         tab.enable_fetch(Some(&patterns), Some(false))?;
 
         let url_owned = url.to_owned();
+        let (body_fetch_retries, body_fetch_delay) =
+            (self.body_fetch_retries, self.body_fetch_delay);
 
         tab.register_response_handling(
             "handler",
@@ -204,21 +381,43 @@ impl TweetFetcher {
                 // trace!("Request Url: {}", req_url);
                 if twitter_def::TWEET_JSON_URL_REGEXP.is_match(req_url) {
                     // trace!("Request Url: {} is matched!!!", req_url);
+                    let status = resp.response.status;
+                    if status == HTTP_TOO_MANY_REQUESTS {
+                        // whatever the body says, in whatever language
+                        if let Err(e) = tx.send((status, String::new())) {
+                            error!("Error sending status to receiver: {}", e);
+                        }
+                        return;
+                    }
                     // contains what we need
                     sleep(Duration::from_millis(10));
                     let mut retries_counter = 0;
                     let body = loop {
-                        let body = fetch_body();
-                        if body.is_ok() {
-                            break body.unwrap();
-                        } else if retries_counter > 6 {
-                            // trace!("Give up for {}", url_owned);
-                            return;
+                        match fetch_body() {
+                            Ok(body) => break body,
+                            Err(_) if retries_counter >= body_fetch_retries => {
+                                // trace!("Give up for {}", url_owned);
+                                return;
+                            }
+                            Err(_) => {}
                         }
                         retries_counter += 1;
-                        sleep(Duration::from_millis(500));
+                        sleep(body_fetch_delay);
+                    };
+                    // chrome already undid the content encoding, base64 only wraps bodies
+                    // it doesn't consider text
+                    let body = if body.base_64_encoded {
+                        match base64::engine::general_purpose::STANDARD.decode(&body.body) {
+                            Ok(v) => String::from_utf8_lossy(&v).into_owned(),
+                            Err(e) => {
+                                error!("Bad base64 response body for {}: {}", url_owned, e);
+                                return;
+                            }
+                        }
+                    } else {
+                        body.body
                     };
-                    if let Err(e) = tx.send(body.body) {
+                    if let Err(e) = tx.send((status, body)) {
                         error!("Error sending body to receiver: {}", e);
                     }
                 }
@@ -227,35 +426,52 @@ impl TweetFetcher {
 
         tab.navigate_to(url)?;
         let recv_result = rx.recv_timeout(Duration::from_secs(30));
-        if let Ok(body) = recv_result {
+        if let Ok((status, body)) = recv_result {
+            let body = if self.expand_threads && body.starts_with('{') {
+                Self::expand_thread(&tab, &rx, body)
+            } else {
+                body
+            };
             tab.stop_loading().unwrap();
             tab.disable_fetch().unwrap();
             tab.deregister_response_handling_all().unwrap();
-            if !body.starts_with('{') {
-                if body.contains("limit") {
+            if status == HTTP_TOO_MANY_REQUESTS {
+                Err(Error::RateLimitExceeded.into())
+            } else if !body.starts_with('{') {
+                let head = body.trim_start().chars().take(16).collect::<String>();
+                let head = head.to_ascii_lowercase();
+                if head.starts_with("<!doctype") || head.starts_with("<html") {
+                    // error page or login redirect, nothing to do with the tweet itself
+                    Err(Error::UnexpectedHtmlResponse.into())
+                } else if body.contains("limit") {
                     Err(Error::RateLimitExceeded.into())
                 } else {
                     Err(Error::CustomError {
-                        msg: "Invalied TweetDetail return".to_string(),
+                        msg: format!(
+                            "Invalied TweetDetail return: {}",
+                            body.chars().take(200).collect::<String>()
+                        ),
                     }
                     .into())
                 }
             } else {
                 let obj: serde_json::Value = serde_json::from_str(body.as_str())
-                    .map_err(|_v| Error::TweetJsonSchemaInvalid)?;
-                let obj = obj.as_object().ok_or(Error::TweetJsonSchemaInvalid)?;
+                    .map_err(|v| Error::TweetJsonSchemaInvalid(Some(v.to_string())))?;
+                let obj = obj.as_object().ok_or(Error::TweetJsonSchemaInvalid(Some(
+                    "root is not an object".to_string(),
+                )))?;
                 if obj.contains_key("errors") {
                     for error in obj["errors"]
                         .as_array()
-                        .ok_or(Error::TweetJsonSchemaInvalid)?
+                        .ok_or(Error::TweetJsonSchemaInvalid(Some(
+                            "errors is not an array".to_string(),
+                        )))?
                     {
                         let msg = error
                             .get("message")
                             .map(|v| v.as_str().unwrap_or(""))
                             .unwrap_or("");
-                        if msg.contains("Rate limit exceeded") {
-                            return Err(Error::RateLimitExceeded.into());
-                        } else if msg.contains("OverCapacity") {
+                        if msg.contains("Rate limit exceeded") || msg.contains("OverCapacity") {
                             return Err(Error::RateLimitExceeded.into());
                         }
                     }
@@ -266,27 +482,71 @@ impl TweetFetcher {
             }
         } else {
             Err(Error::CustomError {
-                msg: format!(
-                    "Cannot wait for data: {}.",
-                    recv_result.unwrap_err().to_string()
-                )
-                .to_string(),
+                msg: format!("Cannot wait for data: {}.", recv_result.unwrap_err()).to_string(),
             }
             .into())
         }
     }
 
+    fn expand_thread(tab: &Tab, rx: &mpsc::Receiver<(u32, String)>, body: String) -> String {
+        const MAX_PAGES: usize = 50;
+        let mut pages = vec![];
+        while pages.len() < MAX_PAGES {
+            if let Err(e) = tab.evaluate("window.scrollTo(0, document.body.scrollHeight);", false)
+            {
+                warn!("Cannot scroll for more thread tweets: {}", e);
+                break;
+            }
+            match rx.recv_timeout(Duration::from_secs(5)) {
+                Ok((_, page)) if page.starts_with('{') => pages.push(page),
+                _ => break,
+            }
+        }
+        if pages.is_empty() {
+            return body;
+        }
+        debug!("Got {} more TweetDetail pages.", pages.len());
+        merge_timeline_entries(&body, &pages).unwrap_or_else(|e| {
+            warn!("Cannot merge thread pages, use the first one only: {}", e);
+            body
+        })
+    }
+
     pub fn get_tweet<'a>(&self, url: &'a str) -> (&'a str, Result<String>) {
         if !url.starts_with("https://twitter.com/") {
             (url, Err(Error::NotATweet.into()))
         } else {
-            (url, self.__get_tweet(url))
+            let diagnostics = if self.debug_browser {
+                self.initial_tab()
+                    .and_then(BrowserDiagnostics::attach)
+                    .map_err(|e| warn!("Cannot watch the browser for {}: {}", url, e))
+                    .ok()
+            } else {
+                None
+            };
+            let started = Instant::now();
+            let result = self.__get_tweet(url);
+            let elapsed = started.elapsed();
+            debug!(
+                "Fetching {} took {} ms ({}).",
+                url,
+                elapsed.as_millis(),
+                if result.is_ok() { "ok" } else { "failed" }
+            );
+            self.timings.lock().unwrap().record(elapsed);
+            if let Some(diagnostics) = diagnostics {
+                if result.is_err() {
+                    diagnostics.dump(url);
+                }
+                diagnostics.detach();
+            }
+            (url, result)
         }
     }
 
     #[allow(dead_code)]
     pub fn sleep(&self, dur: Duration) -> Result<()> {
-        let tab = self.browser_instance.wait_for_initial_tab()?;
+        let tab = self.initial_tab()?;
         tab.stop_loading()?;
         headless_chrome::util::Wait::with_sleep(dur)
             .until::<_, u64>(|| None)
@@ -295,16 +555,159 @@ impl TweetFetcher {
     }
 }
 
+/// Console errors and failed requests of a tab while fetching one tweet, for `--debug-browser`.
+struct BrowserDiagnostics {
+    tab: Arc<Tab>,
+    listener: Weak<dyn EventListener<Event> + Send + Sync>,
+    lines: Arc<Mutex<VecDeque<String>>>,
+}
+
+impl BrowserDiagnostics {
+    /// Only the last lines are kept, the failure is usually near the end.
+    const MAX_LINES: usize = 20;
+
+    fn attach(tab: Arc<Tab>) -> Result<Self> {
+        tab.enable_runtime()?;
+        tab.call_method(Network::Enable {
+            max_total_buffer_size: None,
+            max_resource_buffer_size: None,
+            max_post_data_size: None,
+        })?;
+        let lines = Arc::new(Mutex::new(VecDeque::new()));
+        // loadingFailed only carries the request id
+        let request_urls = Mutex::new(HashMap::new());
+        let push = {
+            let lines = lines.clone();
+            move |line: String| {
+                let mut lines = lines.lock().unwrap();
+                if lines.len() >= Self::MAX_LINES {
+                    lines.pop_front();
+                }
+                lines.push_back(line);
+            }
+        };
+        let listener = tab.add_event_listener(Arc::new(move |event: &Event| match event {
+            Event::RuntimeConsoleAPICalled(e) => {
+                use Runtime::ConsoleAPICalledEventTypeOption as Type;
+                if matches!(e.params.Type, Type::Error | Type::Warning | Type::Assert) {
+                    let text = e
+                        .params
+                        .args
+                        .iter()
+                        .map(|v| match (&v.value, &v.description) {
+                            (Some(serde_json::Value::String(s)), _) => s.clone(),
+                            (Some(value), _) => value.to_string(),
+                            (None, Some(description)) => description.clone(),
+                            (None, None) => format!("{:?}", v.Type),
+                        })
+                        .collect::<Vec<String>>()
+                        .join(" ");
+                    push(format!("console {:?}: {}", e.params.Type, text));
+                }
+            }
+            Event::NetworkRequestWillBeSent(e) => {
+                request_urls
+                    .lock()
+                    .unwrap()
+                    .insert(e.params.request_id.clone(), e.params.request.url.clone());
+            }
+            Event::NetworkLoadingFailed(e) => {
+                let url = request_urls
+                    .lock()
+                    .unwrap()
+                    .get(&e.params.request_id)
+                    .cloned()
+                    .unwrap_or_else(|| format!("request {}", e.params.request_id));
+                push(format!(
+                    "failed {:?} {}: {}{}",
+                    e.params.Type,
+                    url,
+                    e.params.error_text,
+                    if e.params.canceled == Some(true) {
+                        " (canceled)"
+                    } else {
+                        ""
+                    }
+                ));
+            }
+            _ => {}
+        }))?;
+        Ok(Self {
+            tab,
+            listener,
+            lines,
+        })
+    }
+
+    fn dump(&self, url: &str) {
+        let lines = self.lines.lock().unwrap();
+        if lines.is_empty() {
+            debug!("No browser errors while fetching {}.", url);
+            return;
+        }
+        debug!("Browser errors while fetching {}:", url);
+        for line in lines.iter() {
+            debug!("    {}", line);
+        }
+    }
+
+    fn detach(self) {
+        if let Err(e) = self.tab.remove_event_listener(&self.listener) {
+            warn!("Cannot remove the browser listener: {}", e);
+        }
+    }
+}
+
+const HTTP_TOO_MANY_REQUESTS: u32 = 429;
+
+const INSTRUCTIONS_POINTER: &str = "/data/threaded_conversation_with_injections_v2/instructions";
+
+fn timeline_add_entries(obj: &mut serde_json::Value) -> Option<&mut Vec<serde_json::Value>> {
+    obj.pointer_mut(INSTRUCTIONS_POINTER)?
+        .as_array_mut()?
+        .iter_mut()
+        .find(|i| i["type"] == "TimelineAddEntries")?
+        .get_mut("entries")?
+        .as_array_mut()
+}
+
+/// Append the entries of following TweetDetail `pages` into the first response `body`.
+fn merge_timeline_entries(body: &str, pages: &[String]) -> Result<String> {
+    let mut obj: serde_json::Value = serde_json::from_str(body)?;
+    let entries = timeline_add_entries(&mut obj).ok_or_else(|| {
+        Error::TweetJsonSchemaInvalid(Some("no TimelineAddEntries".to_string()))
+    })?;
+    let mut seen = entries
+        .iter()
+        .map(|v| v["entryId"].to_string())
+        .collect::<std::collections::HashSet<String>>();
+    for page in pages {
+        let mut page: serde_json::Value = serde_json::from_str(page)?;
+        if let Some(page_entries) = timeline_add_entries(&mut page) {
+            for entry in page_entries.drain(..) {
+                if seen.insert(entry["entryId"].to_string()) {
+                    entries.push(entry);
+                }
+            }
+        }
+    }
+    Ok(obj.to_string())
+}
+
 pub struct TweetDownloadDB {
     conn_pool: r2d2::Pool<r2d2_sqlite::SqliteConnectionManager>,
 }
 
 impl TweetDownloadDB {
     pub fn new<S: AsRef<Path>>(db_path: S) -> Self {
+        Self::with_tuning(db_path, DBTuning::default())
+    }
+
+    pub fn with_tuning<S: AsRef<Path>>(db_path: S, tuning: DBTuning) -> Self {
         let need_create = !db_path.as_ref().exists();
         let conn_pool = r2d2::Pool::builder()
             .connection_timeout(Duration::from_secs(2 * 60 * 60))
-            .build(r2d2_sqlite::SqliteConnectionManager::file(db_path))
+            .build(tuning.manager(db_path))
             .unwrap();
         if need_create {
             conn_pool
@@ -322,6 +725,31 @@ impl TweetDownloadDB {
                 )
                 .unwrap();
         }
+        conn_pool
+            .get()
+            .unwrap()
+            .execute_batch(
+                r#"
+                    CREATE TABLE IF NOT EXISTS "fetch_fail" (
+                        id INTEGER PRIMARY KEY,
+                        tweet_id INTEGER NOT NULL,
+                        url TEXT NOT NULL,
+                        code TEXT NOT NULL,
+                        detail TEXT NOT NULL,
+                        fail_time INTEGER NOT NULL DEFAULT (STRFTIME('%s', 'now'))
+                    );
+                    CREATE TABLE IF NOT EXISTS "run" (
+                        id INTEGER PRIMARY KEY,
+                        started_at INTEGER NOT NULL,
+                        finished_at INTEGER NOT NULL,
+                        url_count INTEGER NOT NULL,
+                        success INTEGER NOT NULL,
+                        failed INTEGER NOT NULL,
+                        config_json TEXT NOT NULL
+                    );
+                    "#,
+            )
+            .unwrap();
         Self { conn_pool }
     }
 
@@ -346,6 +774,47 @@ impl TweetDownloadDB {
         Ok(())
     }
 
+    /// Log a failed fetch so it can be diagnosed after the run.
+    pub fn insert_fetch_fail(&self, id: u64, url: &str, code: &str, detail: &str) -> Result<()> {
+        self.conn_pool.get()?.execute(
+            r#"INSERT INTO fetch_fail (tweet_id, url, code, detail) VALUES (?1, ?2, ?3, ?4)"#,
+            params![id, url, code, detail],
+        )?;
+        Ok(())
+    }
+
+    /// The latest logged fetch failure of tweet `id` as (code, detail).
+    pub fn get_fetch_fail(&self, id: u64) -> Result<Option<(String, String)>> {
+        let r = self.conn_pool.get()?.query_row(
+            "SELECT code, detail FROM fetch_fail WHERE tweet_id = ?1 ORDER BY id DESC LIMIT 1;",
+            params![id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        );
+        match r {
+            Ok(v) => Ok(Some(v)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Record a fetcher run for auditing, times are sec timestamps.
+    pub fn insert_run(
+        &self,
+        started_at: i64,
+        finished_at: i64,
+        url_count: usize,
+        success: usize,
+        failed: usize,
+        config_json: &str,
+    ) -> Result<()> {
+        self.conn_pool.get()?.execute(
+            r#"INSERT INTO run (started_at, finished_at, url_count, success, failed, config_json)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6)"#,
+            params![started_at, finished_at, url_count, success, failed, config_json],
+        )?;
+        Ok(())
+    }
+
     pub fn get_json(&self, id: u64) -> Result<String> {
         let json = self.conn_pool.get().unwrap().query_row(
             "SELECT json FROM tweet WHERE id = ?1;",
@@ -362,10 +831,52 @@ impl TweetDownloadDB {
         Ok(())
     }
 
+    /// Drop raw json fetched before `cutoff` (sec timestamp) whose tweet is already parsed
+    /// into the TweetDB at `tweet_db`, then reclaim the space. Returns the rows removed.
+    pub fn prune(&self, tweet_db: &Path, cutoff: i64) -> Result<usize> {
+        if !tweet_db.is_file() {
+            return Err(Error::CustomError {
+                msg: format!("TweetDB `{}` not exists", tweet_db.display()),
+            }
+            .into());
+        }
+        let conn = self.conn_pool.get()?;
+        // `?` and `#` would end the path of the uri
+        let uri = format!(
+            "file:{}?mode=ro",
+            tweet_db
+                .to_string_lossy()
+                .replace('%', "%25")
+                .replace('?', "%3f")
+                .replace('#', "%23")
+        );
+        conn.execute("ATTACH DATABASE ?1 AS tw;", params![uri])?;
+        let result = conn.execute(
+            r#"DELETE FROM main.tweet
+                WHERE fetch_time < ?1 AND id IN (SELECT id FROM tw.tweet);"#,
+            params![cutoff],
+        );
+        conn.execute("DETACH DATABASE tw;", [])?;
+        let removed = result?;
+        conn.execute_batch("VACUUM;")?;
+        Ok(removed)
+    }
+
     pub fn get_conn(&self) -> Result<PooledConnection<SqliteConnectionManager>> {
         Ok(self.conn_pool.get()?)
     }
 
+    /// Call `f` with the id and raw json of every fetched tweet, one row at a time.
+    pub fn for_each_json<F: FnMut(u64, &str)>(&self, mut f: F) -> Result<()> {
+        let conn = self.conn_pool.get()?;
+        let mut stmt = conn.prepare(r#"SELECT id, json FROM tweet"#)?;
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            f(row.get(0)?, row.get_ref(1)?.as_str()?);
+        }
+        Ok(())
+    }
+
     pub fn get_urls(&self) -> Result<Vec<String>> {
         let conn = self.conn_pool.get()?;
         let mut stmt = conn.prepare(r#"SELECT url FROM tweet"#)?;
@@ -377,17 +888,71 @@ impl TweetDownloadDB {
     }
 }
 
+/// Pacing of `fetch_url_lists_to_sqlite`, logged out limits are much stricter so each pass
+/// gets its own.
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimitConfig {
+    /// Pause after every request
+    pub request_delay: Duration,
+    /// Take a longer break every this many requests
+    pub batch_size: usize,
+    pub batch_sleep: Duration,
+    /// Sleep on the first rate limit, later ones sleep 10x and grow by 2x of it each time
+    pub backoff: Duration,
+    /// Give up the whole list after this many rate limits in a row instead of sleeping on
+    pub max_consecutive_ratelimits: Option<u32>,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            request_delay: Duration::from_secs(1),
+            batch_size: 100,
+            batch_sleep: Duration::from_secs(10),
+            backoff: Duration::from_secs(60),
+            max_consecutive_ratelimits: None,
+        }
+    }
+}
+
+/// Sleep `secs`, counting down on the progress bar if there is one.
+fn backoff_sleep(secs: u64, progress: Option<&ProgressBar>) {
+    if let Some(bar) = progress {
+        for remain in (1..=secs).rev() {
+            bar.set_message(format!("Rate limit exceeded, resume in {} secs", remain));
+            sleep(Duration::from_secs(1));
+        }
+    } else {
+        sleep(Duration::from_secs(secs));
+    }
+}
+
+/// Fetch `urls` into `dl_db`, returning the succeeded and failed urls and whether it gave up
+/// early on rate limits, in which case every url not fetched is counted as failed.
 pub fn fetch_url_lists_to_sqlite(
     fetcher: &TweetFetcher,
     urls: Vec<String>,
     dl_db: &TweetDownloadDB,
-) -> Result<(Vec<String>, Vec<String>)> {
+    rate_limit: &RateLimitConfig,
+    progress: Option<&ProgressBar>,
+) -> Result<(Vec<String>, Vec<String>, bool)> {
     let mut failed: Vec<String> = vec![];
     let mut succeed: Vec<String> = vec![];
     let total = urls.len();
     let mut counter = 1;
+    if let Some(bar) = progress {
+        bar.set_length(total as u64);
+        bar.set_position(0);
+    }
 
-    for url in urls {
+    let mut aborted = false;
+    let mut urls = urls.into_iter();
+    for url in urls.by_ref() {
+        if let Some(bar) = progress {
+            bar.set_prefix(format!("fetched {} failed {}", succeed.len(), failed.len()));
+            bar.set_message(url.clone());
+            bar.set_position(counter - 1);
+        }
         let id = extract_twitter_url(url.as_str()).unwrap().1;
         if dl_db.is_exist(id) {
             // already existed
@@ -397,28 +962,52 @@ pub fn fetch_url_lists_to_sqlite(
             continue;
         }
 
-        if counter % 100 == 0 {
-            trace!("Every 100 tweet sleep 10 secs...");
-            sleep(Duration::from_secs(10));
+        if rate_limit.batch_size != 0 && counter % rate_limit.batch_size as u64 == 0 {
+            trace!(
+                "Every {} tweet sleep {} secs...",
+                rate_limit.batch_size,
+                rate_limit.batch_sleep.as_secs_f32()
+            );
+            sleep(rate_limit.batch_sleep);
         }
         let mut retries_counter = 0;
+        let mut session_refreshed = false;
         let json = loop {
             let (_, json) = fetcher.get_tweet(&url);
             if let Err(ref err) = json {
                 if let Some(err) = err.downcast_ref::<Error>() {
-                    if let Error::RateLimitExceeded = err {
+                    if let (Error::UnexpectedHtmlResponse, false) = (err, session_refreshed) {
+                        warn!("Got html instead of json for {}, reload home and retry.", url);
+                        // revisiting home renews the session cookies
+                        if let Err(e) = fetcher.acquire_guest_token() {
+                            warn!("Failed to refresh session: {}", e);
+                        }
+                        session_refreshed = true;
+                        continue;
+                    } else if let Error::RateLimitExceeded = err {
+                        if let Some(max) = rate_limit.max_consecutive_ratelimits {
+                            if retries_counter + 1 >= max as u64 {
+                                error!(
+                                    "Rate limit exceeded {} times in a row, stop fetching.",
+                                    retries_counter + 1
+                                );
+                                aborted = true;
+                                break json;
+                            }
+                        }
+                        let backoff = rate_limit.backoff.as_secs();
                         if retries_counter == 0 {
-                            warn!("First Rate limit exeeeded. Sleep 60 secs...");
-                            sleep(Duration::from_secs(60));
+                            warn!("First Rate limit exeeeded. Sleep {} secs...", backoff);
+                            backoff_sleep(backoff, progress);
                             info!("Continue...");
                         } else {
-                            let secs_to_sleep = 600 + 120 * (retries_counter - 1);
+                            let secs_to_sleep = backoff * 10 + backoff * 2 * (retries_counter - 1);
                             warn!(
                                 "{} times Rate limit exceeded. Sleep {} secs...",
                                 retries_counter + 1,
                                 secs_to_sleep
                             );
-                            sleep(Duration::from_secs(secs_to_sleep));
+                            backoff_sleep(secs_to_sleep, progress);
                             info!("Continue...");
                         }
                         retries_counter += 1;
@@ -426,6 +1015,8 @@ pub fn fetch_url_lists_to_sqlite(
                     } else {
                         break json;
                     }
+                } else {
+                    break json;
                 }
             } else {
                 break json;
@@ -435,6 +1026,7 @@ pub fn fetch_url_lists_to_sqlite(
             let result = dl_db.insert(id, url.as_str(), json.as_str());
             if let Err(e) = result {
                 error!("[{}/{}] DB Failed: {} for {}", counter, total, e, url);
+                dl_db.insert_fetch_fail(id, &url, "DB_ERROR", &e.to_string()).ok();
                 failed.push(url);
             } else {
                 info!("[{}/{}] Done: {}", counter, total, url);
@@ -442,12 +1034,32 @@ pub fn fetch_url_lists_to_sqlite(
             }
         } else {
             let err = json.unwrap_err();
-            error!("[{}/{}] Failed: {} for {}", counter, total, err, url);
+            if let Some(Error::BrowserUnavailable) = err.downcast_ref::<Error>() {
+                // every following url would wait for the dead browser again
+                return Err(err);
+            }
+            let code = err.downcast_ref::<Error>().map_or("UNKNOWN", |v| v.code());
+            error!(
+                "[{}/{}] Failed: [{}] {} for {}",
+                counter, total, code, err, url
+            );
+            if let Err(e) = dl_db.insert_fetch_fail(id, &url, code, &err.to_string()) {
+                error!("Error when logging fetch fail for {}: {}", url, e);
+            }
             failed.push(url);
         }
+        if aborted {
+            break;
+        }
         counter += 1;
-        sleep(Duration::from_secs(1));
+        sleep(rate_limit.request_delay);
+    }
+    failed.extend(urls);
+    if let Some(bar) = progress {
+        bar.set_position(total as u64);
+        bar.set_prefix(format!("fetched {} failed {}", succeed.len(), failed.len()));
+        bar.set_message("");
     }
 
-    Ok((succeed, failed))
+    Ok((succeed, failed, aborted))
 }
@@ -1,12 +1,15 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::Formatter;
+use std::path::Path;
+use std::sync::RwLock;
 
 use anyhow::Result;
 use chrono::DateTime;
+use lazy_static::lazy_static;
 use log::{error, trace, warn};
 use serde::Deserialize;
 
-use crate::tweet_db::{Media, ThreadInfo, Tweet};
+use crate::tweet_db::{EditVersion, Media, MediaType, ReplyInfo, ThreadInfo, Tweet, User};
 use crate::twitter_def;
 use crate::utils::Error;
 
@@ -38,6 +41,21 @@ pub struct TweetVideoInfo {
     pub variants: Vec<TweetVideoInfoVariant>,
 }
 
+impl TweetVideoInfo {
+    /// Smallest variant with bitrate at least `min_bitrate`, or the highest one available.
+    pub fn select_variant(&self, min_bitrate: Option<u64>) -> Option<&TweetVideoInfoVariant> {
+        min_bitrate
+            .and_then(|min_bitrate| {
+                // playlists have no bitrate
+                self.variants
+                    .iter()
+                    .filter(|v| v.bitrate > 0 && v.bitrate >= min_bitrate)
+                    .min_by_key(|v| v.bitrate)
+            })
+            .or_else(|| self.variants.iter().max_by_key(|v| v.bitrate))
+    }
+}
+
 #[derive(Deserialize)]
 #[allow(unused)]
 pub struct TweetMedia {
@@ -91,6 +109,7 @@ pub struct TweetLegacy {
     pub full_text: String,
     pub source: Option<String>,
     pub lang: String,
+    #[serde(default)]
     pub display_text_range: Vec<u64>,
     pub favorite_count: u64,
     pub favorited: bool,
@@ -114,6 +133,12 @@ pub struct TweetLegacy {
 pub struct TweetUserLegacy {
     name: String,
     screen_name: String,
+    #[serde(default)]
+    description: String,
+    #[serde(default)]
+    followers_count: u64,
+    #[serde(default)]
+    profile_image_url_https: String,
 }
 
 #[derive(Deserialize)]
@@ -121,6 +146,7 @@ pub struct TweetUserLegacy {
 pub struct TweetUser {
     #[serde(rename = "__typename")]
     typename: String,
+    rest_id: Option<String>,
     legacy: TweetUserLegacy,
 }
 
@@ -134,6 +160,62 @@ pub struct TweetCore {
     user_results: TweetCoreUserResults,
 }
 
+#[derive(Deserialize)]
+#[allow(unused)]
+pub struct TweetViews {
+    // a string of digits, absent on older tweets
+    pub count: Option<String>,
+    pub state: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[allow(unused)]
+pub struct TweetCardImage {
+    pub url: String,
+    pub width: u64,
+    pub height: u64,
+}
+
+#[derive(Deserialize)]
+#[allow(unused)]
+pub struct TweetCardValue {
+    #[serde(rename = "type")]
+    pub _type: String,
+    pub image_value: Option<TweetCardImage>,
+}
+
+#[derive(Deserialize)]
+#[allow(unused)]
+pub struct TweetCardBinding {
+    pub key: String,
+    pub value: TweetCardValue,
+}
+
+#[derive(Deserialize)]
+#[allow(unused)]
+pub struct TweetCardLegacy {
+    pub name: Option<String>,
+    pub url: Option<String>,
+    #[serde(default)]
+    pub binding_values: Vec<TweetCardBinding>,
+}
+
+#[derive(Deserialize)]
+pub struct TweetCard {
+    pub legacy: TweetCardLegacy,
+}
+
+#[derive(Deserialize)]
+#[allow(unused)]
+pub struct TweetEditControl {
+    // every version, the original first
+    #[serde(default)]
+    pub edit_tweet_ids: Vec<String>,
+    pub is_edit_eligible: Option<bool>,
+    // later versions hold the history here instead
+    pub edit_control_initial: Option<Box<TweetEditControl>>,
+}
+
 #[derive(Deserialize)]
 pub struct TweetItem {
     #[serde(rename = "__typename", default = "tweet_type_default")]
@@ -141,6 +223,14 @@ pub struct TweetItem {
     pub rest_id: String,
     pub core: TweetCore,
     pub legacy: TweetLegacy,
+    pub views: Option<TweetViews>,
+    // kept raw, the quoted tweet may be a tombstone
+    #[serde(default)]
+    pub quoted_status_result: Option<JObj>,
+    #[serde(default)]
+    pub edit_control: Option<TweetEditControl>,
+    #[serde(default)]
+    pub card: Option<TweetCard>,
 }
 
 fn tweet_type_default() -> String {
@@ -177,35 +267,127 @@ impl TweetItem {
                 "%a %b %d %H:%M:%S %z %Y",
             )
             .map_or(0, |dt| dt.timestamp() as u64),
+            view_count: self
+                .views
+                .as_ref()
+                .and_then(|v| v.count.as_ref())
+                .and_then(|v| v.parse().ok()),
+            conversation_id: self.legacy.conversation_id_str.parse().ok(),
+            in_reply_to_status_id: self
+                .legacy
+                .in_reply_to_status_id_str
+                .as_ref()
+                .and_then(|v| v.parse().ok()),
+            in_reply_to_screen_name: self.legacy.in_reply_to_screen_name.clone(),
+            lang: Some(self.legacy.lang.clone()),
+            url: None,
+            fetched_via: None,
+            index_time: None,
+            fetch_time: None,
         }
     }
 
-    pub fn as_thread(&self) -> Option<ThreadInfo> {
-        if self.legacy.self_thread.is_none() || self.legacy.in_reply_to_status_id_str.is_none() {
-            None
+    /// `full_text` cut to `display_text_range`, which drops the leading reply mentions and
+    /// trailing media links, with the html entities twitter escapes decoded. Derived on
+    /// demand, the stored `content` stays the untouched `full_text`.
+    pub fn display_text(&self) -> String {
+        let text = match self.legacy.display_text_range[..] {
+            [start, end] => utf16_slice(&self.legacy.full_text, start as usize, end as usize),
+            _ => self.legacy.full_text.as_str(),
+        };
+        let text = text
+            .replace("&lt;", "<")
+            .replace("&gt;", ">")
+            .replace("&amp;", "&");
+        if RTL_LANGS.contains(&self.legacy.lang.as_str()) {
+            // bidi marks are left at the cut points of right-to-left text
+            text.trim_matches(|c: char| c.is_whitespace() || BIDI_MARKS.contains(&c))
+                .to_string()
         } else {
-            Some(ThreadInfo {
+            text.trim().to_string()
+        }
+    }
+
+    pub fn as_user(&self) -> Option<User> {
+        let user = &self.core.user_results.result;
+        Some(User {
+            id: user.rest_id.as_ref()?.parse().ok()?,
+            screen_name: user.legacy.screen_name.clone(),
+            name: user.legacy.name.clone(),
+            bio: user.legacy.description.clone(),
+            followers: user.legacy.followers_count,
+            avatar_url: user.legacy.profile_image_url_https.clone(),
+        })
+    }
+
+    pub fn as_thread(&self) -> Option<ThreadInfo> {
+        match (
+            &self.legacy.self_thread,
+            &self.legacy.in_reply_to_status_id_str,
+        ) {
+            (Some(self_thread), Some(reply_to)) => Some(ThreadInfo {
                 tweet_id: self.rest_id.parse().unwrap(),
-                thread_id: self
-                    .legacy
-                    .self_thread
-                    .as_ref()
-                    .unwrap()
-                    .id_str
-                    .parse()
-                    .unwrap(),
-                reply_to: self
-                    .legacy
-                    .in_reply_to_status_id_str
-                    .as_ref()
-                    .unwrap()
-                    .parse()
-                    .unwrap(),
+                thread_id: self_thread.id_str.parse().unwrap(),
+                reply_to: reply_to.parse().unwrap(),
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn as_reply(&self) -> Option<ReplyInfo> {
+        Some(ReplyInfo {
+            tweet_id: self.rest_id.parse().ok()?,
+            in_reply_to_id: self
+                .legacy
+                .in_reply_to_status_id_str
+                .as_ref()?
+                .parse()
+                .ok()?,
+            in_reply_to_screen_name: self.legacy.in_reply_to_screen_name.clone(),
+        })
+    }
+
+    /// Every version of this tweet if it was edited, empty otherwise.
+    pub fn as_edit_history(&self) -> Vec<EditVersion> {
+        let edit_control = match &self.edit_control {
+            Some(v) => v.edit_control_initial.as_deref().unwrap_or(v),
+            None => return vec![],
+        };
+        let tweet_id = match self.rest_id.parse() {
+            Ok(v) => v,
+            Err(_) => return vec![],
+        };
+        if edit_control.edit_tweet_ids.len() < 2 {
+            return vec![];
+        }
+        edit_control
+            .edit_tweet_ids
+            .iter()
+            .filter_map(|v| v.parse().ok())
+            .enumerate()
+            .map(|(i, version_tweet_id)| EditVersion {
+                tweet_id,
+                version_no: (i + 1) as u32,
+                version_tweet_id,
             })
+            .collect()
+    }
+
+    /// The tweet quoted by this one, if it is included and still visible.
+    pub fn quoted(&self) -> Option<TweetItem> {
+        let mut tweet = self.quoted_status_result.as_ref()?.get("result")?;
+        if tweet["__typename"] == "TweetWithVisibilityResults" {
+            tweet = &tweet["tweet"];
         }
+        if tweet["__typename"] != "Tweet" {
+            return None;
+        }
+        TweetItem::deserialize(tweet)
+            .map_err(|e| warn!("Quoted tweet of {}: {}", self.rest_id, e))
+            .ok()
     }
 
-    pub fn get_medias(&self) -> Vec<Media> {
+    pub fn get_medias(&self, min_video_bitrate: Option<u64>) -> Vec<Media> {
         let medias = if let Some(medias) = &self.legacy.extended_entities {
             medias
         } else {
@@ -217,12 +399,11 @@ impl TweetItem {
                 .iter()
                 .enumerate()
                 .map(|(i, v)| {
-                    let url = if v._type == "animated_gif" || v._type == "video" {
+                    let _type = MediaType::from(v._type.as_str());
+                    let url = if _type.is_video() {
                         let video_info = v.video_info.as_ref().unwrap();
                         video_info
-                            .variants
-                            .iter()
-                            .max_by_key(|v| v.bitrate)
+                            .select_variant(min_video_bitrate)
                             .unwrap()
                             .url
                             .clone()
@@ -237,7 +418,8 @@ impl TweetItem {
                         width: v.original_info.width,
                         height: v.original_info.height,
                         no: (i + 1) as i32,
-                        _type: v._type.to_string(),
+                        thumbnail_url: _type.is_video().then(|| v.media_url_https.clone()),
+                        _type,
                     }
                 })
                 .collect()
@@ -247,15 +429,258 @@ impl TweetItem {
         debug_assert!(medias.len() == self.legacy.entities.media.as_ref().unwrap_or(&vec![]).len());
         medias
     }
+
+    /// Preview image of the link card, numbered after the media entities.
+    pub fn get_card_media(&self) -> Option<Media> {
+        let bindings = &self.card.as_ref()?.legacy.binding_values;
+        let image = twitter_def::CARD_IMAGE_KEYS.iter().find_map(|key| {
+            bindings
+                .iter()
+                .find(|v| v.key == *key)
+                .and_then(|v| v.value.image_value.as_ref())
+        })?;
+        let tweet_id: u64 = self.rest_id.parse().ok()?;
+        let media_count = self.legacy.entities.media.as_ref().map_or(0, |v| v.len());
+        Some(Media {
+            // cards have no media id, there is at most one per tweet
+            id: format!("card_{}", tweet_id),
+            tweet_id,
+            url: image.url.clone(),
+            width: image.width,
+            height: image.height,
+            no: (media_count + 1) as i32,
+            _type: MediaType::CardImage,
+            thumbnail_url: None,
+        })
+    }
+}
+
+const RTL_LANGS: &[&str] = &["ar", "fa", "he", "iw", "ur", "ps", "sd", "ug", "yi", "ckb"];
+const BIDI_MARKS: &[char] = &[
+    '\u{200e}', '\u{200f}', '\u{202a}', '\u{202b}', '\u{202c}', '\u{202d}', '\u{202e}', '\u{2066}',
+    '\u{2067}', '\u{2068}', '\u{2069}',
+];
+
+/// Slice `text` by a range counted in UTF-16 code units, as twitter's offsets are.
+/// Offsets landing inside a surrogate pair are widened to keep the whole character and
+/// offsets past the end are clamped, so emoji are never cut in half.
+fn utf16_slice(text: &str, start: usize, end: usize) -> &str {
+    let byte_offset = |units: usize, round_up: bool| {
+        let mut seen = 0;
+        for (i, c) in text.char_indices() {
+            if units <= seen {
+                return i;
+            }
+            let next = seen + c.len_utf16();
+            if units < next {
+                return if round_up { i + c.len_utf8() } else { i };
+            }
+            seen = next;
+        }
+        text.len()
+    };
+    let from = byte_offset(start, false);
+    let to = byte_offset(end, true).max(from);
+    &text[from..to]
+}
+
+fn schema_invalid<S: Into<String>>(msg: S) -> Error {
+    Error::TweetJsonSchemaInvalid(Some(msg.into()))
+}
+
+/// Walk down `path` from `obj`, reporting the first missing key as a dotted json path.
+fn json_path<'a>(obj: &'a JObj, path: &[&str]) -> std::result::Result<&'a JObj, Error> {
+    let mut cur = obj;
+    for (i, key) in path.iter().enumerate() {
+        cur = cur
+            .get(key)
+            .ok_or_else(|| schema_invalid(format!("{} missing", path[..=i].join("."))))?;
+    }
+    Ok(cur)
+}
+
+/// What a TextTombstone in place of the tweet stands for, tried in this order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TombstoneReason {
+    AccountSuspended,
+    AdultContent,
+    Restricted,
+    AccountNotExisted,
+    Illegal,
+    NotAvailable,
+}
+
+impl TombstoneReason {
+    pub const ALL: &'static [TombstoneReason] = &[
+        Self::AccountSuspended,
+        Self::AdultContent,
+        Self::Restricted,
+        Self::AccountNotExisted,
+        Self::Illegal,
+        Self::NotAvailable,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::AccountSuspended => "account_suspended",
+            Self::AdultContent => "adult_content",
+            Self::Restricted => "restricted",
+            Self::AccountNotExisted => "account_not_existed",
+            Self::Illegal => "illegal",
+            Self::NotAvailable => "not_available",
+        }
+    }
+
+    pub fn from_str(s: &str) -> Option<Self> {
+        Self::ALL.iter().find(|v| v.as_str() == s).copied()
+    }
+
+    fn error(&self) -> Error {
+        match self {
+            Self::AccountSuspended => Error::TwitterAccountSuspended,
+            Self::AdultContent => Error::TweetAdultContent,
+            Self::Restricted => Error::TweetRestricted,
+            Self::AccountNotExisted => Error::TwitterAccountNotExisted,
+            Self::Illegal => Error::TweetIllegalBan,
+            Self::NotAvailable => Error::TweetNotExists,
+        }
+    }
+}
+
+/// Texts a TextTombstone is classified by, keyed by (language, reason). A tombstone containing
+/// any text of a key stands for its reason.
+#[derive(Debug, Clone)]
+pub struct TombstoneMatchers(BTreeMap<(String, TombstoneReason), Vec<String>>);
+
+impl Default for TombstoneMatchers {
+    fn default() -> Self {
+        let mut matchers: BTreeMap<(String, TombstoneReason), Vec<String>> = BTreeMap::new();
+        for (lang, reason, text) in twitter_def::TEXT_TOMBSTONE_MATCHERS {
+            let reason = TombstoneReason::from_str(reason).expect("known tombstone reason");
+            matchers
+                .entry((lang.to_string(), reason))
+                .or_default()
+                .push(text.to_string());
+        }
+        Self(matchers)
+    }
+}
+
+impl TombstoneMatchers {
+    /// The built in matchers with the ones in a TOML table of `[<language>]` tables holding
+    /// `<reason> = ["text", ...]`. A key given there replaces the built in texts of the same
+    /// language and reason, an empty list turns it off.
+    pub fn from_toml(s: &str) -> Result<Self> {
+        let table: HashMap<String, HashMap<String, Vec<String>>> = toml::from_str(s)?;
+        let mut matchers = Self::default();
+        for (lang, reasons) in table {
+            for (reason, texts) in reasons {
+                let reason = TombstoneReason::from_str(&reason).ok_or_else(|| {
+                    let all = TombstoneReason::ALL
+                        .iter()
+                        .map(|v| v.as_str())
+                        .collect::<Vec<&str>>();
+                    anyhow::anyhow!(
+                        "unknown reason `{}` in [{}], expect one of: {}",
+                        reason,
+                        lang,
+                        all.join(", ")
+                    )
+                })?;
+                matchers.0.insert((lang.clone(), reason), texts);
+            }
+        }
+        Ok(matchers)
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        Self::from_toml(&std::fs::read_to_string(path)?)
+            .map_err(|e| anyhow::anyhow!("invalid tombstone matchers `{}`: {}", path.display(), e))
+    }
+
+    /// The language and reason of the first matcher found in `text`.
+    pub fn classify(&self, text: &str) -> Option<(&str, TombstoneReason)> {
+        self.0
+            .iter()
+            .find(|(_, texts)| {
+                texts
+                    .iter()
+                    .any(|v| !v.is_empty() && text.contains(v.as_str()))
+            })
+            .map(|((lang, reason), _)| (lang.as_str(), *reason))
+    }
+}
+
+lazy_static! {
+    static ref TOMBSTONE_MATCHERS: RwLock<TombstoneMatchers> =
+        RwLock::new(TombstoneMatchers::default());
 }
 
+/// Classify tombstones with these instead of the built in matchers from now on.
+pub fn set_tombstone_matchers(matchers: TombstoneMatchers) {
+    *TOMBSTONE_MATCHERS.write().unwrap() = matchers;
+}
+
+/// The failure a TextTombstone in place of the tweet stands for.
+fn tombstone_error(text: &str) -> Error {
+    match TOMBSTONE_MATCHERS.read().unwrap().classify(text) {
+        Some((lang, reason)) => {
+            trace!("Tombstone matched {} text of {}.", lang, reason.as_str());
+            reason.error()
+        }
+        None => Error::TweetUnknownError(text.to_string()),
+    }
+}
+
+const TWEET_RESULT_PATH: &[&str] = &["data", "tweetResult"];
+
+/// Parse a TweetResultByRestId response, which holds the requested tweet only.
+fn extract_tweet_result(id: u64, obj: &JObj) -> Result<HashMap<u64, TweetItem>> {
+    let mut tweet = match json_path(obj, TWEET_RESULT_PATH)?.get("result") {
+        Some(v) => v,
+        // an empty tweetResult is what a deleted tweet looks like
+        None => return Err(Error::TweetNotExists.into()),
+    };
+    if tweet["__typename"] == "TweetWithVisibilityResults" {
+        tweet = &tweet["tweet"];
+    }
+    if tweet["__typename"] == "TweetTombstone" {
+        let text = tweet["tombstone"]["text"]["text"].as_str().unwrap_or("");
+        return Err(tombstone_error(text).into());
+    }
+    if tweet["__typename"] == "TweetUnavailable" {
+        return Err(match tweet["reason"].as_str().unwrap_or("") {
+            "Suspended" => Error::TwitterAccountSuspended,
+            "NsfwLoggedOut" => Error::TweetAdultContent,
+            "Protected" => Error::TweetRestricted,
+            reason => Error::TweetUnknownError(format!("TweetUnavailable: {}", reason)),
+        }
+        .into());
+    }
+    let tweet = TweetItem::deserialize(tweet)
+        .map_err(|e| schema_invalid(format!("{}.result: {}", TWEET_RESULT_PATH.join("."), e)))?;
+    if tweet.rest_id != id.to_string() {
+        return Err(schema_invalid(format!("target tweet {} not found in tweetResult", id)).into());
+    }
+    Ok(HashMap::from([(id, tweet)]))
+}
+
+const INSTRUCTIONS_PATH: &[&str] = &[
+    "data",
+    "threaded_conversation_with_injections_v2",
+    "instructions",
+];
+
 pub fn extract_all_tweets(id: u64, obj: &JObj) -> Result<HashMap<u64, TweetItem>> {
-    let obj = obj.as_object().ok_or(Error::TweetJsonSchemaInvalid)?;
+    let root = obj;
+    let obj = obj
+        .as_object()
+        .ok_or_else(|| schema_invalid("root is not an object"))?;
 
-    let timeline_add_entries = if obj.contains_key("errors") {
+    if obj.contains_key("errors") {
         let errors = obj["errors"]
             .as_array()
-            .ok_or(Error::TweetJsonSchemaInvalid)?;
+            .ok_or_else(|| schema_invalid("errors is not an array"))?;
         for error in errors {
             let message = error["message"].as_str().unwrap_or("");
             if message.contains(twitter_def::TWEET_ERROR_MESSAGE_DELETED) {
@@ -264,64 +689,71 @@ pub fn extract_all_tweets(id: u64, obj: &JObj) -> Result<HashMap<u64, TweetItem>
                 continue;
             }
         }
-
-        obj.get("data")
-            .ok_or(Error::TweetJsonSchemaInvalid)?
-            .get("threaded_conversation_with_injections_v2")
-            .ok_or(Error::TweetJsonSchemaInvalid)?
-            .get("instructions")
-            .ok_or(Error::TweetJsonSchemaInvalid)?
-    } else {
-        obj.get("data")
-            .ok_or(Error::TweetJsonSchemaInvalid)?
-            .get("threaded_conversation_with_injections_v2")
-            .ok_or(Error::TweetJsonSchemaInvalid)?
-            .get("instructions")
-            .ok_or(Error::TweetJsonSchemaInvalid)?
     }
-    .as_array()
-    .ok_or(Error::TweetJsonSchemaInvalid)?
-    .iter()
-    .filter(|i| {
-        let i = i.as_object();
-        i.is_some() && i.unwrap()["type"] == "TimelineAddEntries"
-    })
-    .collect::<Vec<&serde_json::Value>>();
-
-    let entries = if timeline_add_entries.len() == 0 {
-        return Err(Error::TweetJsonSchemaInvalid.into());
+
+    let timeline_add_entries = json_path(root, INSTRUCTIONS_PATH)?
+        .as_array()
+        .ok_or_else(|| schema_invalid(format!("{} is not an array", INSTRUCTIONS_PATH.join("."))))?
+        .iter()
+        .filter(|i| {
+            let i = i.as_object();
+            i.is_some() && i.unwrap()["type"] == "TimelineAddEntries"
+        })
+        .collect::<Vec<&serde_json::Value>>();
+
+    let entries = if timeline_add_entries.is_empty() {
+        return Err(schema_invalid(format!(
+            "{} has no TimelineAddEntries",
+            INSTRUCTIONS_PATH.join(".")
+        ))
+        .into());
     } else if timeline_add_entries.len() > 1 {
         // todo!()
         return Err(Error::Todo("Timelime Add Entries more than once.".to_string()).into());
     } else {
-        timeline_add_entries[0]["entries"]
+        json_path(timeline_add_entries[0], &["entries"])?
             .as_array()
-            .ok_or(Error::TweetJsonSchemaInvalid)?
+            .ok_or_else(|| schema_invalid("TimelineAddEntries.entries is not an array"))?
     };
 
     let mut tweets: HashMap<u64, TweetItem> = HashMap::new();
 
     for entry in entries {
-        let entry = entry.as_object().ok_or(Error::TweetJsonSchemaInvalid)?;
-        let content = &entry["content"]
+        let entry = entry
             .as_object()
-            .ok_or(Error::TweetJsonSchemaInvalid)?;
+            .ok_or_else(|| schema_invalid("entries[] is not an object"))?;
+        let content = &entry["content"].as_object().ok_or_else(|| {
+            schema_invalid(format!("entries[{}].content missing", entry["entryId"]))
+        })?;
 
         let tweet_id = "tweet-".to_string() + &id.to_string();
 
         if content["entryType"] == "TimelineTimelineItem" {
             // single item
             let mut tweet = &content["itemContent"]["tweet_results"]["result"];
-            if(tweet.is_null()) {
-                if entry["entryId"].as_str().unwrap_or("").eq_ignore_ascii_case(&tweet_id) {
+            if tweet.is_null() {
+                if entry["entryId"]
+                    .as_str()
+                    .unwrap_or("")
+                    .eq_ignore_ascii_case(&tweet_id)
+                {
                     trace!("Thread head {} is null. May be an unexisted tweet.", id);
                     return Err(Error::TweetNotExists.into());
                 }
+                trace!("Entry {} has no tweet result, skipped.", entry["entryId"]);
+                continue;
             }
             let mut nested = false;
             if tweet["__typename"] == "TweetWithVisibilityResults" {
                 tweet = &tweet["tweet"];
                 nested = true;
+                if tweet.is_null() {
+                    trace!(
+                        "Entry {} has an empty visibility wrapper.",
+                        entry["entryId"]
+                    );
+                    continue;
+                }
             }
             if tweet["__typename"] != "Tweet" {
                 if tweet["__typename"] == "TweetTombstone" {
@@ -334,23 +766,7 @@ pub fn extract_all_tweets(id: u64, obj: &JObj) -> Result<HashMap<u64, TweetItem>
                             .unwrap()
                             .eq_ignore_ascii_case(&tweet_id)
                         {
-                            return if text.contains(twitter_def::TEXT_TOMBSTONE_ACCOUNT_SUSPENDED) {
-                                Err(Error::TwitterAccountSuspended.into())
-                            } else if text.contains(twitter_def::TEXT_TOMBSTONE_AUDLT_CONTENT) {
-                                Err(Error::TweetAdultContent.into())
-                            } else if text.contains(twitter_def::TEXT_TOMBSTONE_USER_RESTRICTED) {
-                                Err(Error::TweetRestricted.into())
-                            } else if text.contains(twitter_def::TEXT_TOMBSTONE_ACCOUNT_NOT_EXISTED)
-                            {
-                                Err(Error::TwitterAccountNotExisted.into())
-                            } else if text.contains(twitter_def::TEXT_TOMBSTONE_TWEET_ILLEGAL) {
-                                Err(Error::TweetIllegalBan.into())
-                            } else if text.contains(twitter_def::TEXT_TOMBSTONE_TWEET_NOT_AVALIABLE)
-                            {
-                                Err(Error::TweetNotExists.into())
-                            } else {
-                                Err(Error::TweetUnknownError(text.to_string()).into())
-                            }
+                            return Err(tombstone_error(text).into());
                         }
                     } else {
                         return Err(Error::TweetUnknownError(
@@ -368,14 +784,19 @@ pub fn extract_all_tweets(id: u64, obj: &JObj) -> Result<HashMap<u64, TweetItem>
                     continue;
                 }
             }
-            let tweet = TweetItem::deserialize(tweet).or_else(|v| {
+            let tweet = TweetItem::deserialize(tweet).map_err(|v| {
                 error!("{}", v);
-                Err(Error::TweetJsonSchemaInvalid)
+                schema_invalid(format!(
+                    "entries[{}].content.itemContent.tweet_results.result: {}",
+                    entry["entryId"], v
+                ))
+            })?;
+            let id = tweet.rest_id.parse::<u64>().map_err(|_v| {
+                schema_invalid(format!(
+                    "entries[{}] rest_id `{}` is not a number",
+                    entry["entryId"], tweet.rest_id
+                ))
             })?;
-            let id = tweet
-                .rest_id
-                .parse::<u64>()
-                .or_else(|_v| Err(Error::TweetJsonSchemaInvalid))?;
             tweets.insert(id, tweet);
         } else if content["entryType"] == "TimelineTimelineModule" {
             // multiple item
@@ -386,33 +807,76 @@ pub fn extract_all_tweets(id: u64, obj: &JObj) -> Result<HashMap<u64, TweetItem>
             }
             let items = items.unwrap();
             for item in items {
-                let mut tweet = &item["item"]["itemContent"]["tweet_results"]["result"];
+                let item_content = &item["item"]["itemContent"];
+                if twitter_def::SKIPPABLE_MODULE_ITEM_TYPES
+                    .iter()
+                    .any(|v| item_content["itemType"] == *v)
+                {
+                    trace!(
+                        "Entry {}, item {} skipped, type {} ({}).",
+                        entry["entryId"],
+                        item["entryId"],
+                        item_content["itemType"],
+                        item_content["cursorType"]
+                    );
+                    continue;
+                }
+                let mut tweet = &item_content["tweet_results"]["result"];
+                if tweet.is_null() {
+                    trace!(
+                        "Entry {}, item {} has no tweet result, skipped.",
+                        entry["entryId"],
+                        item["entryId"]
+                    );
+                    continue;
+                }
                 let mut nested = false;
                 if tweet["__typename"] == "TweetWithVisibilityResults" {
                     tweet = &tweet["tweet"];
                     nested = true;
-                }
-                if tweet["__typename"] != "Tweet" {
-                    if !nested {
+                    if tweet.is_null() {
                         trace!(
-                            "Entry {}, item {} is not a tweet. but {}.",
+                            "Entry {}, item {} has an empty visibility wrapper.",
                             entry["entryId"],
-                            item["entryId"],
-                            tweet["__typename"]
+                            item["entryId"]
                         );
                         continue;
                     }
                 }
-                let tweet = TweetItem::deserialize(tweet).or_else(|v| {
+                if tweet["__typename"] != "Tweet" && !nested {
+                    trace!(
+                        "Entry {}, item {} is not a tweet. but {}.",
+                        entry["entryId"],
+                        item["entryId"],
+                        tweet["__typename"]
+                    );
+                    continue;
+                }
+                let tweet = TweetItem::deserialize(tweet).map_err(|v| {
                     error!("{}", v);
-                    Err(Error::TweetJsonSchemaInvalid)
+                    schema_invalid(format!(
+                        "entries[{}].content.items[{}].item.itemContent.tweet_results.result: {}",
+                        entry["entryId"], item["entryId"], v
+                    ))
+                })?;
+                let id = tweet.rest_id.parse::<u64>().map_err(|_v| {
+                    schema_invalid(format!(
+                        "entries[{}].content.items[{}] rest_id `{}` is not a number",
+                        entry["entryId"], item["entryId"], tweet.rest_id
+                    ))
                 })?;
-                let id = tweet
-                    .rest_id
-                    .parse::<u64>()
-                    .or_else(|_v| Err(Error::TweetJsonSchemaInvalid))?;
                 tweets.insert(id, tweet);
             }
+        } else if twitter_def::SKIPPABLE_ENTRY_TYPES
+            .iter()
+            .any(|v| content["entryType"] == *v)
+        {
+            trace!(
+                "Entry {} skipped, type {}.",
+                entry["entryId"],
+                content["entryType"]
+            );
+            continue;
         } else {
             // unimplemented!();
             return Err(Error::Unimplemented(format!(
@@ -423,13 +887,94 @@ pub fn extract_all_tweets(id: u64, obj: &JObj) -> Result<HashMap<u64, TweetItem>
         }
     }
 
-    if !tweets.contains_key(&id) {
-        Err(Error::TweetJsonSchemaInvalid.into())
+    if tweets.is_empty() {
+        // deleted tweets sometimes come back as a well formed timeline holding only cursors
+        trace!("No tweet in entries of {}. May be a deleted tweet.", id);
+        Err(Error::TweetNotExists.into())
+    } else if !tweets.contains_key(&id) {
+        Err(schema_invalid(format!("target tweet {} not found in entries", id)).into())
     } else {
         Ok(tweets)
     }
 }
 
+/// Parse a raw TweetDetail or TweetResultByRestId response body, see `extract_all_tweets`.
+pub fn parse_tweet_json(id: u64, raw: &str) -> Result<HashMap<u64, TweetItem>> {
+    let obj: JObj =
+        serde_json::from_str(raw).map_err(|e| Error::JsonFailed(Some(e.to_string())))?;
+    if obj["data"].get("tweetResult").is_some() {
+        extract_tweet_result(id, &obj)
+    } else {
+        extract_all_tweets(id, &obj)
+    }
+}
+
+/// Parse a payload captured by another tool, either a whole TweetDetail or
+/// TweetResultByRestId response or a single tweet result. Also returns the tweet a whole
+/// response was parsed for, as only those can be stored as raw json in the download db.
+pub fn parse_external_json(raw: &str) -> Result<(Option<u64>, HashMap<u64, TweetItem>)> {
+    let obj: JObj =
+        serde_json::from_str(raw).map_err(|e| Error::JsonFailed(Some(e.to_string())))?;
+    if let Some(rest_id) = json_path(&obj, TWEET_RESULT_PATH).ok().and_then(|v| {
+        v.pointer("/result/rest_id")
+            .or(v.pointer("/result/tweet/rest_id"))
+    }) {
+        let id = rest_id
+            .as_str()
+            .and_then(|v| v.parse::<u64>().ok())
+            .ok_or_else(|| schema_invalid(format!("rest_id `{}` is not a number", rest_id)))?;
+        extract_tweet_result(id, &obj).map(|v| (Some(id), v))
+    } else if obj.get("data").is_some() {
+        // any tweet of the conversation works as the target
+        let ids = json_path(&obj, INSTRUCTIONS_PATH)?
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|i| i["entries"].as_array())
+            .flatten()
+            .filter_map(|e| {
+                e["entryId"]
+                    .as_str()?
+                    .strip_prefix("tweet-")?
+                    .parse::<u64>()
+                    .ok()
+            })
+            .collect::<Vec<u64>>();
+        let mut result = Err(schema_invalid("no tweet entry in TweetDetail payload").into());
+        for id in ids {
+            result = extract_all_tweets(id, &obj).map(|v| (Some(id), v));
+            if result.is_ok() {
+                break;
+            }
+        }
+        result
+    } else {
+        let mut tweet = &obj;
+        if let Some(v) = tweet.get("tweet_results") {
+            tweet = v;
+        }
+        if let Some(v) = tweet.get("result") {
+            tweet = v;
+        }
+        if tweet["__typename"] == "TweetWithVisibilityResults" {
+            tweet = &tweet["tweet"];
+        }
+        if tweet["__typename"] != "Tweet" {
+            return Err(schema_invalid(format!(
+                "single tweet result is {}, not a Tweet",
+                tweet["__typename"]
+            ))
+            .into());
+        }
+        let tweet = TweetItem::deserialize(tweet)
+            .map_err(|e| schema_invalid(format!("single tweet result: {}", e)))?;
+        let id = tweet.rest_id.parse::<u64>().map_err(|_| {
+            schema_invalid(format!("rest_id `{}` is not a number", tweet.rest_id))
+        })?;
+        Ok((None, HashMap::from([(id, tweet)])))
+    }
+}
+
 pub fn get_thread(id: u64, tweets: &HashMap<u64, TweetItem>) -> Option<Vec<u64>> {
     if !tweets.contains_key(&id) {
         return None;
@@ -1,30 +1,58 @@
 use lazy_static::lazy_static;
 use regex::Regex;
 
-pub const LOGIN_URL: &'static str = "https://twitter.com/i/flow/login";
-pub const LOGIN_USERNAME_SELECTOR: &'static str = r#"input[autocomplete*="username"]"#;
-pub const LOGIN_PASSWORD_SELECTOR: &'static str = r#"input[autocomplete*="password"]"#;
-pub const LOGIN_VALIDATE_SELECTOR: &'static str = r#"input[data-testid="ocfEnterTextTextInput"]"#;
-pub const LOGIN_BUTTON_SELECTOR_NEXT: &'static str =
+pub const LOGIN_URL: &str = "https://twitter.com/i/flow/login";
+pub const HOME_URL: &str = "https://twitter.com/";
+pub const GUEST_TOKEN_COOKIE: &str = "gt";
+pub const LOGIN_USERNAME_SELECTOR: &str = r#"input[autocomplete*="username"]"#;
+pub const LOGIN_PASSWORD_SELECTOR: &str = r#"input[autocomplete*="password"]"#;
+pub const LOGIN_VALIDATE_SELECTOR: &str = r#"input[data-testid="ocfEnterTextTextInput"]"#;
+pub const LOGIN_BUTTON_SELECTOR_NEXT: &str =
     r#"div[role="button"][style*="background-color"]"#;
-pub const LOGIN_BUTTON_SELECTOR_VERIFY: &'static str =
+pub const LOGIN_BUTTON_SELECTOR_VERIFY: &str =
     r#"div[role="button"][data-testid="ocfEnterTextNextButton"]"#;
-pub const LOGIN_BUTTON_SELECTOR_LOGIN: &'static str =
+pub const LOGIN_BUTTON_SELECTOR_LOGIN: &str =
     r#"div[role="button"][data-testid="LoginForm_Login_Button"]"#;
+pub const LOGIN_CHALLENGE_SELECTOR: &str =
+    r#"iframe[id="arkose_iframe"], iframe[src*="arkoselabs"], iframe[src*="funcaptcha"]"#;
+/// Machine names looked up in netrc for the login credentials.
+pub const NETRC_MACHINES: &[&str] = &["twitter.com", "x.com"];
+/// Timeline entry types carrying no tweet content (pagination cursors and the like).
+pub const SKIPPABLE_ENTRY_TYPES: &[&str] = &["TimelineTimelineCursor"];
+/// Module item types carrying no tweet content, e.g. the "show more replies" cursor of a
+/// conversation with collapsed replies.
+pub const SKIPPABLE_MODULE_ITEM_TYPES: &[&str] = &["TimelineTimelineCursor"];
+/// Card `binding_values` keys holding the preview image, largest first.
+pub const CARD_IMAGE_KEYS: &[&str] = &[
+    "photo_image_full_size_original",
+    "thumbnail_image_original",
+    "photo_image_full_size",
+];
 lazy_static! {
-    pub static ref TWEET_JSON_URL_REGEXP: Regex =
-        Regex::new(r#"https://(api\.)?twitter.com/(i/api/)?graphql/.*?/TweetDetail"#).unwrap();
+    pub static ref TWEET_JSON_URL_REGEXP: Regex = Regex::new(
+        r#"https://(api\.)?twitter.com/(i/api/)?graphql/.*?/(TweetDetail|TweetResultByRestId)"#
+    )
+    .unwrap();
     pub static ref TWEET_URL_EXTRACTOR: Regex =
         Regex::new(r#"https://twitter.com/(.*?)/status/(\d*)"#).unwrap();
 }
 
-pub const TEXT_TOMBSTONE_ACCOUNT_SUSPENDED: &'static str = r#"这个帖子来自一个被冻结的账号。"#;
-pub const TEXT_TOMBSTONE_ACCOUNT_NOT_EXISTED: &'static str = r#"这个帖子来自一个已不存在的账号。"#;
-pub const TEXT_TOMBSTONE_AUDLT_CONTENT: &'static str =
-    r#"受年龄限制的成人内容。这些内容可能不适合 18 岁以下的用户。"#;
-pub const TEXT_TOMBSTONE_USER_RESTRICTED: &'static str =
-    r#"该账号所有者限制了可以查看其帖子的用户。"#;
-
-pub const TEXT_TOMBSTONE_TWEET_ILLEGAL: &'static str = r#"这条推文违反了 Twitter 规则"#;
-pub const TEXT_TOMBSTONE_TWEET_NOT_AVALIABLE: &'static str = r#"此推文不可用"#;
-pub const TWEET_ERROR_MESSAGE_DELETED: &'static str = r#"_Missing: No status found with that ID."#;
+/// Built in TextTombstone matchers: (language, reason, text the tombstone contains).
+/// `--tombstone-matchers` can replace or add to them without a rebuild.
+pub const TEXT_TOMBSTONE_MATCHERS: &[(&str, &str, &str)] = &[
+    ("zh", "account_suspended", r#"这个帖子来自一个被冻结的账号。"#),
+    ("zh", "adult_content", r#"受年龄限制的成人内容。这些内容可能不适合 18 岁以下的用户。"#),
+    ("zh", "restricted", r#"该账号所有者限制了可以查看其帖子的用户。"#),
+    ("zh", "account_not_existed", r#"这个帖子来自一个已不存在的账号。"#),
+    ("zh", "illegal", r#"这条推文违反了 Twitter 规则"#),
+    ("zh", "not_available", r#"此推文不可用"#),
+    ("en", "account_suspended", r#"is from a suspended account"#),
+    ("en", "adult_content", r#"Age-restricted adult content"#),
+    ("en", "restricted", r#"account owner limits who can view their"#),
+    ("en", "account_not_existed", r#"is from an account that no longer exists"#),
+    ("en", "illegal", r#"violated the Twitter Rules"#),
+    ("en", "illegal", r#"violated the X Rules"#),
+    ("en", "not_available", r#"Tweet is unavailable"#),
+    ("en", "not_available", r#"Post is unavailable"#),
+];
+pub const TWEET_ERROR_MESSAGE_DELETED: &str = r#"_Missing: No status found with that ID."#;
@@ -1,17 +1,22 @@
 use crate::tweet_db::TweetFailReason;
 use crate::twitter_def;
 use anyhow::Result;
+use clap::{ArgMatches, FromArgMatches};
 use lazy_static::lazy_static;
-use log::info;
+use log::{info, LevelFilter};
 use regex::Regex;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
 use std::fmt::{Display, Formatter};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 #[derive(Debug)]
-#[allow(unused)]
+#[allow(unused, clippy::enum_variant_names)]
 pub enum Error {
     CustomError { msg: String },
     LoginFailed { msg: String },
+    LoginChallenge,
     TweetNotExists,
     TwitterAccountSuspended,
     TwitterAccountNotExisted,
@@ -22,10 +27,12 @@ pub enum Error {
     TweetParseFailed(Option<String>),
     TweetUnknownError(String),
     JsonFailed(Option<String>),
-    TweetJsonSchemaInvalid,
+    TweetJsonSchemaInvalid(Option<String>),
     Todo(String),
     Unimplemented(String),
     RateLimitExceeded,
+    UnexpectedHtmlResponse,
+    BrowserUnavailable,
     DBError,
 }
 
@@ -34,6 +41,7 @@ impl Display for Error {
         match self {
             Error::CustomError { msg } => write!(f, "ShiroTweet Error: {}.", msg),
             Error::LoginFailed { msg } => write!(f, "Login failed: {}.", msg),
+            Error::LoginChallenge => write!(f, "Login failed: captcha encountered."),
             Error::TweetNotExists => write!(f, "Tweet does not exist."),
             Error::TwitterAccountSuspended => write!(f, "Twitter accound is suspended."),
             Error::TweetRestricted => write!(f, "Tweet is restricted by author."),
@@ -54,10 +62,22 @@ impl Display for Error {
                     write!(f, "Tweet json data parse failed.")
                 }
             }
-            Error::TweetJsonSchemaInvalid => write!(f, "Tweet json schema invalid."),
+            Error::TweetJsonSchemaInvalid(msg) => {
+                if let Some(msg) = msg {
+                    write!(f, "Tweet json schema invalid: {}.", msg)
+                } else {
+                    write!(f, "Tweet json schema invalid.")
+                }
+            }
             Error::Todo(msg) => write!(f, "Todo: {}.", msg),
             Error::Unimplemented(msg) => write!(f, "Unimplemented: {}.", msg),
             Error::RateLimitExceeded => write!(f, "Rate limit exceeded."),
+            Error::UnexpectedHtmlResponse => {
+                write!(f, "Got a html page instead of tweet json, session may be broken.")
+            }
+            Error::BrowserUnavailable => {
+                write!(f, "Browser is unavailable, chrome may have crashed.")
+            }
             Error::TweetAdultContent => write!(f, "Tweet adult content, need login."),
             Error::TwitterAccountNotExisted => write!(f, "Twitter account not existed."),
             Error::DBError => write!(f, "Database error."),
@@ -68,6 +88,32 @@ impl Display for Error {
 impl std::error::Error for Error {}
 
 impl Error {
+    /// Stable identifier for logs and external tooling, unlike the `Display` text.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::CustomError { .. } => "CUSTOM_ERROR",
+            Self::LoginFailed { .. } => "LOGIN_FAILED",
+            Self::LoginChallenge => "LOGIN_CHALLENGE",
+            Self::TweetNotExists => "TWEET_NOT_EXISTS",
+            Self::TwitterAccountSuspended => "ACCOUNT_SUSPENDED",
+            Self::TwitterAccountNotExisted => "ACCOUNT_NOT_EXISTED",
+            Self::TweetAdultContent => "TWEET_ADULT_CONTENT",
+            Self::TweetRestricted => "TWEET_RESTRICTED",
+            Self::TweetIllegalBan => "TWEET_ILLEGAL_BAN",
+            Self::NotATweet => "NOT_A_TWEET",
+            Self::TweetParseFailed(_) => "TWEET_PARSE_FAILED",
+            Self::TweetUnknownError(_) => "TWEET_UNKNOWN_ERROR",
+            Self::JsonFailed(_) => "JSON_FAILED",
+            Self::TweetJsonSchemaInvalid(_) => "TWEET_JSON_SCHEMA_INVALID",
+            Self::Todo(_) => "TODO",
+            Self::Unimplemented(_) => "UNIMPLEMENTED",
+            Self::RateLimitExceeded => "RATE_LIMIT",
+            Self::UnexpectedHtmlResponse => "UNEXPECTED_HTML_RESPONSE",
+            Self::BrowserUnavailable => "BROWSER_UNAVAILABLE",
+            Self::DBError => "DB_ERROR",
+        }
+    }
+
     pub fn try_make_fail_reason(&self) -> Option<TweetFailReason> {
         match self {
             Self::TweetRestricted => Some(TweetFailReason::Restricted),
@@ -80,21 +126,32 @@ impl Error {
     }
 }
 
+pub const CHECKSUM_ALGO: &str = "sha256";
+
+/// Hex digest of everything `reader` yields, with `CHECKSUM_ALGO`.
+pub fn checksum<R: std::io::Read>(mut reader: R) -> Result<String> {
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut reader, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// `-v` raises `default` one level per occurrence, `-q` lowers it to warnings.
+pub fn log_level(default: LevelFilter, verbose: u8, quiet: bool) -> LevelFilter {
+    if quiet {
+        return LevelFilter::Warn;
+    }
+    match verbose {
+        0 => default,
+        1 => std::cmp::max(default, LevelFilter::Debug),
+        _ => LevelFilter::Trace,
+    }
+}
+
 pub fn extract_twitter_url(url: &str) -> Option<(&str, u64)> {
     if let Some(capt) = twitter_def::TWEET_URL_EXTRACTOR.captures(url) {
         let username = capt.get(1).unwrap().as_str();
-        let status_id = capt.get(2);
-        if status_id.is_none() {
-            None
-        } else {
-            let status_id = status_id.unwrap();
-            let status_id = status_id.as_str().parse::<u64>();
-            if status_id.is_err() {
-                None
-            } else {
-                Some((username, status_id.unwrap()))
-            }
-        }
+        let status_id = capt.get(2)?.as_str().parse::<u64>().ok()?;
+        Some((username, status_id))
     } else {
         None
     }
@@ -105,23 +162,248 @@ lazy_static! {
         Regex::new(r#"(https://twitter.com/.*?/status/\d+)\b"#).unwrap();
 }
 
-pub fn read_url_list<P: AsRef<Path>>(url_list_path: P) -> Result<Vec<String>> {
+/// The tweet url in a line of the url list, if any.
+pub fn extract_list_url(line: &str) -> Option<String> {
+    URL_EXTRACTOR
+        .captures(line)
+        .map(|m| m.get(1).unwrap().as_str().to_string())
+}
+
+/// Read tweet urls from a list file and drop duplicates. With `keep_order` the first occurrence
+/// of each url stays where it was, otherwise the urls are sorted.
+pub fn read_url_list<P: AsRef<Path>>(url_list_path: P, keep_order: bool) -> Result<Vec<String>> {
     info!("Reading url list from {}", url_list_path.as_ref().display());
     let mut urls = std::fs::read_to_string(url_list_path)?
         .lines()
-        .map(|v| {
-            if let Some(m) = URL_EXTRACTOR.captures(v) {
-                Some(m.get(1).unwrap().as_str().to_string())
-            } else {
-                None
-            }
-        })
-        .filter(|p| p.is_some())
-        .map(|p| p.unwrap())
+        .filter_map(extract_list_url)
         .collect::<Vec<String>>();
     info!("Raw has {} entries.", urls.len());
-    urls.sort();
-    urls.dedup();
-    info!("Sorted and deduped has {} entries.", urls.len());
+    if keep_order {
+        let mut seen = HashSet::new();
+        urls.retain(|url| seen.insert(url.clone()));
+        info!("Deduped has {} entries.", urls.len());
+    } else {
+        urls.sort();
+        urls.dedup();
+        info!("Sorted and deduped has {} entries.", urls.len());
+    }
     Ok(urls)
 }
+
+/// Write `url\tcontent` lines of the tweets without media. Tabs, newlines and backslashes
+/// in the content are escaped so every tweet stays on one line.
+pub fn write_no_media_list<P: AsRef<Path>>(path: P, tweets: &[(String, String)]) -> Result<()> {
+    let content: String = tweets
+        .iter()
+        .map(|(url, content)| {
+            let content = content
+                .replace('\\', "\\\\")
+                .replace('\t', "\\t")
+                .replace('\r', "\\r")
+                .replace('\n', "\\n");
+            format!("{}\t{}\n", url, content)
+        })
+        .collect();
+    std::fs::write(&path, content)?;
+    info!(
+        "{} tweets without media written to {}",
+        tweets.len(),
+        path.as_ref().display()
+    );
+    Ok(())
+}
+
+/// Where media of `tweet_db` go when no destination is given: `<db stem>_media` next to it.
+pub fn default_media_dir(tweet_db: &Path) -> PathBuf {
+    let mut name = tweet_db.file_stem().unwrap_or_default().to_os_string();
+    name.push("_media");
+    tweet_db.with_file_name(name)
+}
+
+/// `~/.netrc`, or `~/_netrc` on windows.
+pub fn default_netrc_path() -> Option<PathBuf> {
+    let home = std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE"))?;
+    let home = Path::new(&home);
+    let netrc = home.join(".netrc");
+    if cfg!(windows) && !netrc.exists() {
+        Some(home.join("_netrc"))
+    } else {
+        Some(netrc)
+    }
+}
+
+/// Login and password of the first netrc entry for one of `machines`, falling back to the
+/// `default` entry.
+pub fn read_netrc<P: AsRef<Path>>(
+    path: P,
+    machines: &[&str],
+) -> Result<Option<(Option<String>, Option<String>)>> {
+    let content = std::fs::read_to_string(path)?;
+    // macro definitions run until the next blank line
+    let mut in_macdef = false;
+    let mut tokens = content
+        .lines()
+        .flat_map(|line| {
+            if in_macdef || line.split_whitespace().next() == Some("macdef") {
+                in_macdef = !line.trim().is_empty();
+                return vec![];
+            }
+            line.split_whitespace().collect()
+        })
+        .collect::<Vec<&str>>()
+        .into_iter();
+    let mut entries: Vec<(Option<String>, Option<String>, Option<String>)> = vec![];
+    while let Some(token) = tokens.next() {
+        match token {
+            "machine" => entries.push((tokens.next().map(str::to_string), None, None)),
+            "default" => entries.push((None, None, None)),
+            "login" | "password" | "account" => {
+                let value = tokens.next().map(str::to_string);
+                if let Some(entry) = entries.last_mut() {
+                    match token {
+                        "login" => entry.1 = value,
+                        "password" => entry.2 = value,
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    let entry = entries
+        .iter()
+        .find(|v| v.0.as_deref().is_some_and(|m| machines.contains(&m)))
+        .or_else(|| entries.iter().find(|v| v.0.is_none()));
+    Ok(entry.map(|v| (v.1.clone(), v.2.clone())))
+}
+
+/// Parse `argv` again with the options in the TOML `config` file added as command line
+/// arguments, so clap checks them like the rest. Options `matches` got from the command line
+/// win over the file, and keys naming no option are an error.
+pub fn apply_config_file<T: FromArgMatches>(
+    command: &clap::Command,
+    argv: &[OsString],
+    matches: &ArgMatches,
+    config: &Path,
+) -> Result<T> {
+    let table: toml::value::Table = toml::from_str(&std::fs::read_to_string(config)?)?;
+    let mut argv = argv.to_vec();
+    let mut positionals = vec![];
+    for (key, value) in table {
+        // clap names the options in kebab case
+        let id = key.replace('_', "-");
+        let arg = match command.get_arguments().find(|v| v.get_id() == id) {
+            Some(arg) if key != "config" => arg,
+            _ => return Err(anyhow::anyhow!("unknown option `{}`", key)),
+        };
+        if matches.value_source(&id) == Some(clap::ValueSource::CommandLine) {
+            continue;
+        }
+        let values = match value {
+            toml::Value::Array(v) => v,
+            v => vec![v],
+        };
+        for value in values {
+            let value = match value {
+                toml::Value::String(v) => v,
+                v => v.to_string(),
+            };
+            if arg.is_positional() {
+                positionals.push(value);
+                continue;
+            }
+            let flag = format!("--{}", arg.get_long().unwrap_or(&id));
+            if !matches!(
+                arg.get_action(),
+                clap::ArgAction::SetTrue | clap::ArgAction::Count
+            ) {
+                argv.push(format!("{}={}", flag, value).into());
+            } else {
+                // a flag is set by true, a counted one is repeated
+                let times = match value.as_str() {
+                    "true" => 1,
+                    "false" => 0,
+                    v => v.parse::<usize>().map_err(|_| {
+                        anyhow::anyhow!("`{}` is a flag, expect true or false", key)
+                    })?,
+                };
+                argv.extend(std::iter::repeat_n(OsString::from(&flag), times));
+            }
+        }
+    }
+    argv.extend(positionals.into_iter().map(OsString::from));
+    let matches = command.clone().try_get_matches_from(argv).map_err(|e| {
+        // only the reason, without the usage clap appends
+        let e = e.to_string();
+        let reason = e.lines().next().unwrap_or_default();
+        anyhow::anyhow!("{}", reason.trim_start_matches("error: "))
+    })?;
+    Ok(T::from_arg_matches(&matches)?)
+}
+
+/// On-disk name of the media at `url`, its last path segment. The query and fragment only
+/// belong to the request url, `?name=orig` must never end up in a filename.
+pub fn media_filename(url: &str) -> String {
+    let path = url.split(['?', '#']).next().unwrap_or("");
+    sanitize_path_component(path.rsplit('/').next().unwrap_or(""))
+}
+
+/// Lowest media id of every `(folder, filename)` in `medias`, the one that keeps the plain name.
+pub fn filename_owners<'a, I>(medias: I) -> HashMap<(String, String), String>
+where
+    I: IntoIterator<Item = (&'a str, &'a str, &'a str)>,
+{
+    let mut owners: HashMap<(String, String), String> = HashMap::new();
+    for (folder, filename, media_id) in medias {
+        let owner = owners
+            .entry((folder.to_string(), filename.to_string()))
+            .or_insert_with(|| media_id.to_string());
+        // media ids are decimal, a shorter one is a smaller one
+        if (media_id.len(), media_id) < (owner.len(), owner.as_str()) {
+            *owner = media_id.to_string();
+        }
+    }
+    owners
+}
+
+/// `filename`, or `<media id>_<filename>` when a lower media id owns it in `folder`. Which media
+/// keeps the plain name doesn't depend on the order they are read in.
+pub fn dedup_filename(
+    owners: &HashMap<(String, String), String>,
+    folder: &str,
+    filename: String,
+    media_id: &str,
+) -> String {
+    match owners.get(&(folder.to_string(), filename.clone())) {
+        Some(v) if v != media_id => format!("{}_{}", media_id, filename),
+        _ => filename,
+    }
+}
+
+const WINDOWS_RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Make `name` usable as a single path component on every common filesystem.
+pub fn sanitize_path_component(name: &str) -> String {
+    let name = name
+        .chars()
+        .map(|c| match c {
+            '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect::<String>();
+    // Windows strips trailing dots and spaces
+    let name = name.trim_end_matches(['.', ' ']);
+    if name.is_empty() {
+        return "_".to_string();
+    }
+    let stem = name.split('.').next().unwrap_or("");
+    if WINDOWS_RESERVED_NAMES.iter().any(|v| v.eq_ignore_ascii_case(stem)) {
+        format!("_{}", name)
+    } else {
+        name.to_string()
+    }
+}
@@ -0,0 +1,90 @@
+//! `--config` TOML files applied on top of the command line options.
+
+#[path = "../src/tweet_db.rs"]
+#[allow(dead_code, unused)]
+mod tweet_db;
+#[path = "../src/twitter_def.rs"]
+#[allow(dead_code, unused)]
+mod twitter_def;
+#[path = "../src/utils.rs"]
+#[allow(dead_code, unused)]
+mod utils;
+
+use clap::{CommandFactory, Parser};
+use std::ffi::OsString;
+use tweet_db::DBTuning;
+
+#[derive(Parser, Debug)]
+struct Args {
+    #[clap(long, default_value = "1")]
+    commit_batch: usize,
+    #[clap(long, value_parser = clap::value_parser!(u64).range(1..))]
+    max_tweets: Option<u64>,
+    #[clap(long, action, conflicts_with = "login-only")]
+    no_login: bool,
+    #[clap(long, action)]
+    login_only: bool,
+    #[clap(long, action = clap::ArgAction::Append)]
+    chrome_arg: Vec<String>,
+    #[clap(flatten)]
+    db_tuning: DBTuning,
+}
+
+fn apply(cli: &[&str], config: &str) -> anyhow::Result<Args> {
+    let path = std::env::temp_dir().join(format!(
+        "shirotweets-config-{}-{}.toml",
+        std::process::id(),
+        cli.len() + config.len()
+    ));
+    std::fs::write(&path, config).unwrap();
+    let argv = std::iter::once("test")
+        .chain(cli.iter().copied())
+        .map(OsString::from)
+        .collect::<Vec<_>>();
+    let matches = Args::command().get_matches_from(&argv);
+    let result = utils::apply_config_file(&Args::command(), &argv, &matches, &path);
+    std::fs::remove_file(&path).ok();
+    result
+}
+
+#[test]
+fn sets_flattened_options() {
+    let args = apply(&[], "commit_batch = 10\nsqlite_cache_size = 512\n").unwrap();
+    assert_eq!(args.commit_batch, 10);
+    assert_eq!(args.db_tuning.sqlite_cache_size, 512);
+    assert_eq!(args.db_tuning.sqlite_mmap_size, 256);
+}
+
+#[test]
+fn command_line_wins() {
+    let args = apply(
+        &["--sqlite-cache-size", "128"],
+        "sqlite_cache_size = 512\nsqlite_mmap_size = 0\n",
+    )
+    .unwrap();
+    assert_eq!(args.db_tuning.sqlite_cache_size, 128);
+    assert_eq!(args.db_tuning.sqlite_mmap_size, 0);
+}
+
+#[test]
+fn unknown_option_is_an_error() {
+    assert!(apply(&[], "sqlite_page_size = 4096\n").is_err());
+}
+
+#[test]
+fn sets_flags_and_repeated_options() {
+    let args = apply(&[], "no_login = true\nchrome_arg = [\"--a\", \"--b\"]\n").unwrap();
+    assert!(args.no_login);
+    assert_eq!(args.chrome_arg, ["--a", "--b"]);
+}
+
+#[test]
+fn conflicts_with_the_command_line() {
+    assert!(apply(&["--login-only"], "no_login = true\n").is_err());
+}
+
+#[test]
+fn values_are_validated() {
+    assert!(apply(&[], "max_tweets = 0\n").is_err());
+    assert_eq!(apply(&[], "max_tweets = 3\n").unwrap().max_tweets, Some(3));
+}
@@ -0,0 +1,88 @@
+//! On-disk names the downloader gives to media urls.
+
+#[path = "../src/tweet_db.rs"]
+#[allow(dead_code, unused)]
+mod tweet_db;
+#[path = "../src/twitter_def.rs"]
+#[allow(dead_code, unused)]
+mod twitter_def;
+#[path = "../src/utils.rs"]
+#[allow(dead_code, unused)]
+mod utils;
+
+use utils::{dedup_filename, filename_owners, media_filename};
+
+#[test]
+fn query_is_stripped() {
+    assert_eq!(
+        media_filename("https://pbs.twimg.com/media/FMxAbCdVQAA1234.jpg?name=orig"),
+        "FMxAbCdVQAA1234.jpg"
+    );
+    assert_eq!(
+        media_filename("https://video.twimg.com/ext_tw_video/1/pu/vid/1280x720/high.mp4?tag=12"),
+        "high.mp4"
+    );
+    assert_eq!(
+        media_filename("https://pbs.twimg.com/card_img/1/XyZ12345?format=jpg&name=orig"),
+        "XyZ12345"
+    );
+}
+
+#[test]
+fn fragment_is_stripped() {
+    assert_eq!(
+        media_filename("https://pbs.twimg.com/media/AbC.png#frag?x=1"),
+        "AbC.png"
+    );
+}
+
+#[test]
+fn plain_url_is_kept() {
+    assert_eq!(
+        media_filename("https://pbs.twimg.com/media/AbC.png"),
+        "AbC.png"
+    );
+}
+
+#[test]
+fn never_contains_query_characters() {
+    for url in [
+        "https://pbs.twimg.com/media/AbC.jpg?name=orig?name=orig",
+        "https://pbs.twimg.com/media/?name=orig",
+        "AbC.jpg?name=orig",
+        "",
+    ] {
+        let name = media_filename(url);
+        assert!(!name.is_empty(), "{}", url);
+        assert!(
+            !name.contains(['?', '&', '=']),
+            "{}",
+            url
+        );
+    }
+}
+
+#[test]
+fn colliding_names_do_not_depend_on_order() {
+    let medias = [
+        ("a", "high.mp4", "1500000000000000002"),
+        ("a", "high.mp4", "900000000000000001"),
+        ("b", "high.mp4", "1500000000000000003"),
+    ];
+    for order in [medias.to_vec(), medias.iter().rev().cloned().collect()] {
+        let owners = filename_owners(order.iter().cloned());
+        let names = order
+            .iter()
+            .map(|(folder, filename, media_id)| {
+                dedup_filename(&owners, folder, filename.to_string(), media_id)
+            })
+            .collect::<Vec<String>>();
+        for ((_, _, media_id), name) in order.iter().zip(names) {
+            let expected = match *media_id {
+                "1500000000000000002" => "1500000000000000002_high.mp4",
+                _ => "high.mp4",
+            };
+            assert_eq!(name, expected, "{}", media_id);
+        }
+    }
+}
@@ -0,0 +1,131 @@
+//! Round trips through the SQLite store.
+
+#[path = "../src/tweet_db.rs"]
+#[allow(dead_code, unused)]
+mod tweet_db;
+#[path = "../src/twitter_def.rs"]
+#[allow(dead_code, unused)]
+mod twitter_def;
+#[path = "../src/utils.rs"]
+#[allow(dead_code, unused)]
+mod utils;
+
+use std::path::PathBuf;
+use tweet_db::{ThreadInfo, Tweet, TweetDB, TweetFailReason, TweetStore};
+
+fn temp_db(name: &str) -> (PathBuf, TweetDB) {
+    let path = std::env::temp_dir().join(format!(
+        "shirotweets-{}-{}.sqlite",
+        name,
+        std::process::id()
+    ));
+    let _ = std::fs::remove_file(&path);
+    let db = TweetDB::new(&path).unwrap();
+    (path, db)
+}
+
+fn tweet(id: u64) -> Tweet {
+    Tweet {
+        id,
+        author: "a".into(),
+        content: String::new(),
+        create_time: id,
+        view_count: None,
+        conversation_id: Some(1),
+        in_reply_to_status_id: None,
+        in_reply_to_screen_name: None,
+        lang: None,
+        url: None,
+        fetched_via: None,
+        index_time: None,
+        fetch_time: None,
+    }
+}
+
+#[test]
+fn fail_reason_round_trip() {
+    for reason in TweetFailReason::ALL {
+        assert_eq!(TweetFailReason::from_str(reason.as_str()), Some(*reason));
+        assert_eq!(TweetFailReason::try_from(reason.to_string()), Ok(*reason));
+    }
+    assert_eq!(TweetFailReason::from_str("unknown"), None);
+}
+
+#[test]
+fn every_fail_reason_passes_the_check() {
+    let (path, db) = temp_db("fail-reasons");
+    for (i, reason) in TweetFailReason::ALL.iter().enumerate() {
+        db.insert_fail(&format!("https://twitter.com/a/status/{}", i + 1), *reason);
+    }
+    let stored = db
+        .list_fails(&[])
+        .unwrap()
+        .into_iter()
+        .map(|v| (v.tweet_id, v.reason))
+        .collect::<Vec<_>>();
+    drop(db);
+    std::fs::remove_file(&path).unwrap();
+    let mut expected = TweetFailReason::ALL
+        .iter()
+        .enumerate()
+        .map(|(i, v)| (i as u64 + 1, *v))
+        .collect::<Vec<_>>();
+    expected.sort_by_key(|v| v.1.as_str());
+    assert_eq!(stored, expected);
+}
+
+#[test]
+fn thread_edge_to_a_missing_tweet_is_skipped() {
+    let (path, db) = temp_db("thread-edges");
+    db.insert_tweet(&tweet(1));
+    db.insert_tweet(&tweet(2));
+    db.insert_tweet(&tweet(3));
+    db.insert_thread(&ThreadInfo {
+        tweet_id: 2,
+        thread_id: 1,
+        reply_to: 1,
+    });
+    // the parent 9 was never fetched
+    db.insert_thread(&ThreadInfo {
+        tweet_id: 3,
+        thread_id: 1,
+        reply_to: 9,
+    });
+    let ids = db
+        .get_thread_tweets(1)
+        .unwrap()
+        .into_iter()
+        .map(|v| v.id)
+        .collect::<Vec<_>>();
+    drop(db);
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(ids, vec![1, 2]);
+}
+
+#[test]
+fn merged_update_moves_past_the_export_state() {
+    let index_seq = |db: &TweetDB| -> u64 {
+        db.get_db_conn()
+            .query_row("SELECT index_seq FROM tweet WHERE id = 1", [], |row| {
+                row.get(0)
+            })
+            .unwrap()
+    };
+    let (path, db) = temp_db("merge-target");
+    let (other_path, other) = temp_db("merge-source");
+    db.insert_tweet(&tweet(1));
+    other.insert_tweet(&tweet(1));
+    // fetched again later
+    other
+        .get_db_conn()
+        .execute("UPDATE tweet SET fetch_time = fetch_time + 60", [])
+        .unwrap();
+    let exported = index_seq(&db);
+    drop(other);
+    db.merge_from(&other_path).unwrap();
+    let merged = index_seq(&db);
+    drop(db);
+    std::fs::remove_file(&path).unwrap();
+    std::fs::remove_file(&other_path).unwrap();
+    assert!(merged > exported);
+}